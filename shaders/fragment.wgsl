@@ -12,27 +12,79 @@ struct Planet {
     pos: vec3<f32>,
     radius: f32,
     color: vec3<f32>,
-    padding: f32,
+    mass: f32,
+    surface_type: u32,
+    seed: u32,
+    feature_scale: f32,
+    displacement: f32,
+    // Per channel scattering strength, blue scattering most gives blue skies and red sunsets
+    atmosphere_color: vec3<f32>,
+    // Thickness of the atmosphere as a fraction of the radius, 0 for none
+    atmosphere_height: f32,
+    atmosphere_density: f32,
+    // Bodies with a luminosity glow and light everything else
+    luminosity: f32,
+    // Ring plane through the center, no ring when the outer radius is 0
+    ring_normal: vec3<f32>,
+    ring_inner_radius: f32,
+    ring_color: vec3<f32>,
+    ring_outer_radius: f32,
+    ring_opacity: f32,
+    // Body to world rotation, the body spins about its +y
+    orientation: vec4<f32>,
 }
 
+// Must match SurfaceType in surface.rs
+const SURFACE_PLAIN: u32 = 0u;
+const SURFACE_ROCKY: u32 = 1u;
+const SURFACE_GAS_GIANT: u32 = 2u;
+const SURFACE_ICY: u32 = 3u;
+const SURFACE_LAVA: u32 = 4u;
+
 struct Planets {
-    planets: array<Planet, 5>,
-    /* planet_count: u32,
-    padding: f32, */
+    planets: array<Planet>,
+}
+
+// Must match BvhNode in bvh.rs, the left child of an inner node is the next node
+struct BvhNode {
+    min: vec3<f32>,
+    // Body index for leaves
+    body: u32,
+    max: vec3<f32>,
+    // Right child, 0 for leaves
+    right: u32,
 }
 
+// Must match bvh::MAX_DEPTH
+const BVH_STACK_SIZE: u32 = 32u;
+
 struct Uniforms {
     iMouse: vec2<f32>,
     iResolution: vec2<f32>,
     iTime: f32,
     planet_count: u32,
+    // Rays per pixel, spread over the pixel to smooth silhouettes
+    samples: u32,
+    render_mode: u32,
+    // Reference plane colored by the gravitational potential, 0 when hidden
+    potential_plane: u32,
+    plane_height: f32,
+    // Contour lines per doubling of the potential
+    contour_density: f32,
+    padding: f32,
 }
 
+// Must match RenderMode in renderer.rs
+const RENDER_MODE_RAY_MARCH: u32 = 0u;
+const RENDER_MODE_ANALYTIC: u32 = 1u;
+
 @group(0) @binding(0)
 var<uniform> camera: Camera;
 
 @group(1) @binding(0)
-var<uniform> planets: Planets;
+var<storage, read> planets: Planets;
+@group(1) @binding(1)
+var<storage, read> bvh: array<BvhNode>;
 
 @group(2) @binding(0)
 var<uniform> uniforms : Uniforms;
@@ -42,17 +94,41 @@ var tex_diffuse: texture_2d<f32>;
 @group(3) @binding(1)
 var samp_diffuse: sampler;
 
-fn map(p : vec3f) -> vec4<f32> {
-    var spherePosition = planets.planets[0].pos;
+// Distance to the closest planet and which planet it is
+struct Hit {
+    distance: f32,
+    index: i32,
+}
+
+fn map(p : vec3f) -> Hit {
+    var hit = Hit(1e9, 0);
+
+    // Walk the hierarchy, skipping boxes further away than the closest body so far
+    var stack: array<u32, BVH_STACK_SIZE>;
+    stack[0] = 0u;
+    var top = 1u;
+    while (top > 0u) {
+        top--;
+        let node = bvh[stack[top]];
+        if (boxDistance(p, node) >= hit.distance) {
+            continue;
+        }
+
+        if (node.right == 0u) {
+            let current_distance = planetDistance(i32(node.body), p);
+            if (current_distance < hit.distance) {
+                hit = Hit(current_distance, i32(node.body));
+            }
+            continue;
+        }
 
-    var current_min = sdSphere(p - spherePosition, planets.planets[0].radius);
-    var current_min_color = planets.planets[0].color;
-    for (var i = 1; i < i32(uniforms.planet_count); i++) {
-        let sphere_position = planets.planets[i].pos;
-        let current_distance = sdSphere(p-sphere_position, planets.planets[i].radius);
-        if (current_distance < current_min) {
-            current_min = current_distance;
-            current_min_color = planets.planets[i].color;
+        // The nearer child goes on top, so finding its body first culls more of the other
+        let left = stack[top] + 1u;
+        let leftCloser = boxDistance(p, bvh[left]) <= boxDistance(p, bvh[node.right]);
+        if (top + 2u <= BVH_STACK_SIZE) {
+            stack[top] = select(left, node.right, leftCloser);
+            stack[top + 1u] = select(node.right, left, leftCloser);
+            top += 2u;
         }
     }
     
@@ -65,8 +141,38 @@ fn map(p : vec3f) -> vec4<f32> {
         current_min_color = vec3f(0.1) + textureSample(tex_diffuse, samp_diffuse, uv).xyz;
     } */
 
-    // return sphere;
-    return vec4f(current_min_color, current_min);
+    return hit;
+}
+
+// Distance to the box, 0 inside it
+fn boxDistance(p: vec3f, node: BvhNode) -> f32 {
+    return length(max(max(node.min - p, p - node.max), vec3f(0.0)));
+}
+
+fn planetDistance(i: i32, p: vec3f) -> f32 {
+    let planet = planets.planets[i];
+    let sphere = sdSphere(p - planet.pos, planet.radius);
+
+    // Terrain stays within amplitude of the sphere, so the noise is only needed close to it
+    let amplitude = planet.radius * planet.displacement;
+    if (amplitude <= 0.0) {
+        return sphere;
+    }
+    if (sphere > amplitude * 2.0) {
+        return sphere - amplitude;
+    }
+
+    let direction = toBodyFrame(planet, normalize(p - planet.pos));
+    let height = fbm(direction * planet.feature_scale, planet.seed) * 2.0 - 1.0;
+    // The noise can change faster than the distance, shorter steps keep the march from overshooting
+    return (sphere - amplitude * height) * 0.75;
+}
+
+// Undo the body's rotation, taking a world direction into its frame
+fn toBodyFrame(planet: Planet, v: vec3f) -> vec3f {
+    let q = vec4f(-planet.orientation.xyz, planet.orientation.w);
+    let t = 2.0 * cross(q.xyz, v);
+    return v + q.w * t + cross(q.xyz, t);
 }
 
 fn sdSphere(position : vec3f, s : f32) -> f32 {
@@ -83,17 +189,660 @@ fn rot2D(angle : f32) -> mat2x2<f32>{
     return mat2x2<f32>(c, -s, s, c);
 }
 
-fn getNormal(position : vec3f) -> vec3f {
+// Equirectangular lookup by world space direction, must match sky::direction_to_uv
+fn sky_uv(direction: vec3f) -> vec2f {
+    let u = atan2(direction.x, direction.z) / (2.0 * 3.14159265) + 0.5;
+    let v = acos(clamp(direction.y, -1.0, 1.0)) / 3.14159265;
+    return vec2f(u, v);
+}
+
+// Integer hash from "Hash Functions for GPU Rendering" (Jarzynski and Olano)
+fn pcg3d(input: vec3u) -> vec3u {
+    var v = input * 1664525u + 1013904223u;
+    v.x += v.y * v.z;
+    v.y += v.z * v.x;
+    v.z += v.x * v.y;
+    v ^= v >> vec3u(16u);
+    v.x += v.y * v.z;
+    v.y += v.z * v.x;
+    v.z += v.x * v.y;
+    return v;
+}
+
+// Value noise in 0..1
+fn noise(p: vec3f, seed: u32) -> f32 {
+    let cell = floor(p);
+    let f = p - cell;
+    let t = f * f * (3.0 - 2.0 * f);
+    let base = bitcast<vec3u>(vec3i(cell)) + vec3u(seed, 0u, 0u);
+
+    var corners: array<f32, 8>;
+    for (var i = 0u; i < 8u; i++) {
+        let offset = vec3u(i & 1u, (i >> 1u) & 1u, (i >> 2u) & 1u);
+        corners[i] = f32(pcg3d(base + offset).x) / 4294967295.0;
+    }
+
+    let x0 = mix(corners[0], corners[1], t.x);
+    let x1 = mix(corners[2], corners[3], t.x);
+    let x2 = mix(corners[4], corners[5], t.x);
+    let x3 = mix(corners[6], corners[7], t.x);
+    return mix(mix(x0, x1, t.y), mix(x2, x3, t.y), t.z);
+}
+
+// Five octaves of noise, still in 0..1
+fn fbm(p: vec3f, seed: u32) -> f32 {
+    var value = 0.0;
+    var amplitude = 0.5;
+    var frequency = 1.0;
+    for (var octave = 0u; octave < 5u; octave++) {
+        value += amplitude * noise(p * frequency, seed + octave);
+        amplitude *= 0.5;
+        frequency *= 2.0;
+    }
+    return value / 0.96875;
+}
+
+// 1 along the middle of the noise, used for cracks and lava channels
+fn ridge(p: vec3f, seed: u32) -> f32 {
+    return 1.0 - abs(fbm(p, seed) * 2.0 - 1.0);
+}
+
+struct SurfaceColor {
+    albedo: vec3f,
+    emission: vec3f,
+}
+
+fn surfaceColor(index: i32, position: vec3f) -> SurfaceColor {
+    let planet = planets.planets[index];
+    let color = planet.color;
+    // Direction from the center in the body's frame, so the pattern moves and turns with the body
+    let local = toBodyFrame(planet, normalize(position - planet.pos));
+    let p = local * planet.feature_scale;
+
+    switch planet.surface_type {
+        case SURFACE_ROCKY: {
+            let height = fbm(p, planet.seed);
+            let detail = fbm(p * 4.0, planet.seed + 7u);
+            let ground = color * (0.45 + 0.8 * height) * (0.85 + 0.3 * detail);
+            // Low ground reads as dark basins
+            let albedo = mix(color * 0.35, ground, smoothstep(0.35, 0.45, height));
+            return SurfaceColor(albedo, vec3f(0.0));
+        }
+        case SURFACE_GAS_GIANT: {
+            let turbulence = fbm(p * 2.0, planet.seed);
+            let band = sin((local.y * planet.feature_scale * 6.0 + turbulence * 1.5) * 3.14159265);
+            let albedo = mix(color * 0.55, mix(color, vec3f(1.0), 0.35), band * 0.5 + 0.5);
+            return SurfaceColor(albedo, vec3f(0.0));
+        }
+        case SURFACE_ICY: {
+            let ice = mix(color, vec3f(0.92, 0.96, 1.0), 0.65) * (0.9 + 0.1 * fbm(p * 3.0, planet.seed + 3u));
+            let cracks = smoothstep(0.9, 0.98, ridge(p * 2.0, planet.seed));
+            return SurfaceColor(mix(ice, color * 0.4, cracks * 0.7), vec3f(0.0));
+        }
+        case SURFACE_LAVA: {
+            let crust = color * (0.1 + 0.1 * fbm(p * 4.0, planet.seed + 5u));
+            let glow = smoothstep(0.94, 0.99, ridge(p * 1.5, planet.seed));
+            return SurfaceColor(crust * (1.0 - glow), glow * vec3f(2.0, 0.7, 0.1));
+        }
+        default: {
+            return SurfaceColor(color, vec3f(0.0));
+        }
+    }
+}
+
+// Only the body that was hit is sampled, the others can't change its surface
+fn getNormal(index: i32, position : vec3f) -> vec3f {
     let d = vec2f(0.01, 0.0);
-    let gradientX = map(position + d.xyy).w - map(position - d.xyy).w;
-    let gradientY = map(position + d.yxy).w - map(position - d.yxy).w;
-    let gradientZ = map(position + d.yyx).w - map(position - d.yyx).w;
+    let gradientX = planetDistance(index, position + d.xyy) - planetDistance(index, position - d.xyy);
+    let gradientY = planetDistance(index, position + d.yxy) - planetDistance(index, position - d.yxy);
+    let gradientZ = planetDistance(index, position + d.yyx) - planetDistance(index, position - d.yyx);
     let normal = vec3f(gradientX, gradientY, gradientZ);
     return normalize(normal);
 }
 
+// Distance along the ray to where it enters and leaves a sphere, x > y when it misses
+fn raySphere(origin: vec3f, direction: vec3f, center: vec3f, radius: f32) -> vec2f {
+    let oc = origin - center;
+    let b = dot(oc, direction);
+    let c = dot(oc, oc) - radius * radius;
+    let h = b * b - c;
+    if (h < 0.0) {
+        return vec2f(1.0, -1.0);
+    }
+    let s = sqrt(h);
+    return vec2f(-b - s, -b + s);
+}
+
+struct Light {
+    direction: vec3f,
+    distance: f32,
+    color: vec3f,
+    index: i32,
+}
+
+fn hasEmissiveBodies() -> bool {
+    for (var i = 0; i < i32(uniforms.planet_count); i++) {
+        if (planets.planets[i].luminosity > 0.0) {
+            return true;
+        }
+    }
+    return false;
+}
+
+// Without any emissive bodies (old recordings for example) the scene keeps the original fixed light
+fn lightCount(fallback: bool) -> i32 {
+    return select(i32(uniforms.planet_count), 1, fallback);
+}
+
+// Light from body i as seen from p, black if the body doesn't glow
+fn getLight(i: i32, p: vec3f, fallback: bool) -> Light {
+    if (fallback) {
+        return Light(normalize(vec3f(1.0, 132.0 / 255.0, 0.0)), 1e9, vec3f(1.0), -1);
+    }
+    let planet = planets.planets[i];
+    let offset = planet.pos - p;
+    // Tinted a little towards the body's color. No falloff with distance, the system isn't to scale
+    let color = mix(vec3f(1.0), planet.color, 0.3) * max(planet.luminosity, 0.0);
+    return Light(normalize(offset), length(offset) - planet.radius, color, i);
+}
+
+fn hasRing(planet: Planet) -> bool {
+    return planet.ring_outer_radius > planet.ring_inner_radius && planet.ring_opacity > 0.0;
+}
+
+// Opacity at a distance from the center, with bands and gaps from noise and soft edges
+fn ringOpacity(planet: Planet, r: f32) -> f32 {
+    if (r < planet.ring_inner_radius || r > planet.ring_outer_radius) {
+        return 0.0;
+    }
+    let u = (r - planet.ring_inner_radius) / (planet.ring_outer_radius - planet.ring_inner_radius);
+    let coarse = noise(vec3f(u * 12.0, 0.0, 0.0), planet.seed + 11u);
+    let fine = noise(vec3f(u * 60.0, 0.0, 0.0), planet.seed + 13u);
+    let bands = smoothstep(0.25, 0.75, coarse) * (0.5 + 0.5 * fine);
+    let edges = smoothstep(0.0, 0.05, u) * (1.0 - smoothstep(0.95, 1.0, u));
+    return planet.ring_opacity * bands * edges;
+}
+
+// Where a ray crosses the ring plane of a body, negative when it doesn't
+fn ringDistance(origin: vec3f, direction: vec3f, planet: Planet) -> f32 {
+    let facing = dot(direction, planet.ring_normal);
+    if (abs(facing) < 1e-6) {
+        return -1.0;
+    }
+    return dot(planet.pos - origin, planet.ring_normal) / facing;
+}
+
+// How much of the light reaches p: 0 behind another body, partly through rings.
+// The ignored body (the one p is on) can't block the light but its ring still can
+fn lightVisibility(p: vec3f, light: Light, ignored: i32) -> f32 {
+    var visibility = 1.0;
+    for (var i = 0; i < i32(uniforms.planet_count); i++) {
+        if (i == light.index) {
+            continue;
+        }
+        let planet = planets.planets[i];
+        if (i != ignored) {
+            let t = raySphere(p, light.direction, planet.pos, planet.radius);
+            if (t.x <= t.y && t.y > 0.0 && t.x < light.distance) {
+                return 0.0;
+            }
+        }
+        if (hasRing(planet)) {
+            // Points on the ring itself sit on the plane, the small offset stops them shadowing themselves
+            let t = ringDistance(p, light.direction, planet);
+            if (t > 0.001 * planet.radius && t < light.distance) {
+                visibility *= 1.0 - ringOpacity(planet, length(p + light.direction * t - planet.pos));
+            }
+        }
+    }
+    return visibility;
+}
+
+// Rings in front of whatever the ray hit, lit from both sides and shadowed like everything else.
+// Like atmospheres they are layered in body order
+fn applyRings(origin: vec3f, direction: vec3f, maxDistance: f32, background: vec3f, fallback: bool) -> vec3f {
+    var color = background;
+    for (var i = 0; i < i32(uniforms.planet_count); i++) {
+        let planet = planets.planets[i];
+        if (!hasRing(planet)) {
+            continue;
+        }
+
+        let t = ringDistance(origin, direction, planet);
+        if (t <= 0.0 || t >= maxDistance) {
+            continue;
+        }
+        let p = origin + direction * t;
+        let r = length(p - planet.pos);
+        let opacity = ringOpacity(planet, r);
+        if (opacity <= 0.0) {
+            continue;
+        }
+
+        var lighting = vec3f(0.0);
+        for (var j = 0; j < lightCount(fallback); j++) {
+            let light = getLight(j, p, fallback);
+            if (light.index == i || all(light.color == vec3f(0.0))) {
+                continue;
+            }
+            let visibility = lightVisibility(p, light, -1);
+            lighting += light.color * visibility * (0.25 + 0.75 * abs(dot(planet.ring_normal, light.direction)));
+        }
+
+        let u = (r - planet.ring_inner_radius) / (planet.ring_outer_radius - planet.ring_inner_radius);
+        let shade = 0.8 + 0.4 * noise(vec3f(u * 60.0, 0.0, 0.0), planet.seed + 13u);
+        color = mix(color, planet.ring_color * shade * lighting, opacity);
+    }
+    return color;
+}
+
+const ATMOSPHERE_STEPS: i32 = 12;
+const LIGHT_STEPS: i32 = 4;
+
+fn hasAtmosphere(planet: Planet) -> bool {
+    return planet.atmosphere_height > 0.0 && planet.atmosphere_density > 0.0;
+}
+
+// Thins out exponentially with altitude, density is the optical depth straight up from the ground
+fn atmosphereDensity(planet: Planet, p: vec3f) -> f32 {
+    let thickness = planet.radius * planet.atmosphere_height;
+    let altitude = max(length(p - planet.pos) - planet.radius, 0.0);
+    return planet.atmosphere_density * 4.0 / thickness * exp(-4.0 * altitude / thickness);
+}
+
+// Amount of atmosphere between p and space in a direction
+fn opticalDepth(planet: Planet, p: vec3f, direction: vec3f) -> f32 {
+    let shell = raySphere(p, direction, planet.pos, planet.radius * (1.0 + planet.atmosphere_height));
+    let stepSize = max(shell.y, 0.0) / f32(LIGHT_STEPS);
+    var depth = 0.0;
+    for (var i = 0; i < LIGHT_STEPS; i++) {
+        depth += atmosphereDensity(planet, p + direction * stepSize * (f32(i) + 0.5)) * stepSize;
+    }
+    return depth;
+}
+
+// Single scattering along the view ray through every atmosphere it crosses before maxDistance.
+// Atmospheres are applied in body order, which is only wrong where two of them overlap on screen
+fn applyAtmospheres(origin: vec3f, direction: vec3f, maxDistance: f32, background: vec3f, fallback: bool) -> vec3f {
+    var color = background;
+    for (var i = 0; i < i32(uniforms.planet_count); i++) {
+        let planet = planets.planets[i];
+        if (!hasAtmosphere(planet)) {
+            continue;
+        }
+
+        let shell = raySphere(origin, direction, planet.pos, planet.radius * (1.0 + planet.atmosphere_height));
+        let start = max(shell.x, 0.0);
+        let end = min(shell.y, maxDistance);
+        if (end <= start) {
+            continue;
+        }
+
+        let stepSize = (end - start) / f32(ATMOSPHERE_STEPS);
+        var viewDepth = 0.0;
+        var inscattered = vec3f(0.0);
+        for (var step = 0; step < ATMOSPHERE_STEPS; step++) {
+            let p = origin + direction * (start + stepSize * (f32(step) + 0.5));
+            let density = atmosphereDensity(planet, p) * stepSize;
+            viewDepth += density * 0.5;
+
+            for (var j = 0; j < lightCount(fallback); j++) {
+                let light = getLight(j, p, fallback);
+                if (all(light.color == vec3f(0.0))) {
+                    continue;
+                }
+                let visibility = lightVisibility(p, light, -1);
+                if (visibility <= 0.0) {
+                    continue;
+                }
+                let lightDepth = opticalDepth(planet, p, light.direction);
+                let transmittance = visibility * exp(-planet.atmosphere_color * (viewDepth + lightDepth));
+                // Rayleigh phase function, scaled to average 1 rather than 1 / 4pi so the glow is visible
+                let cosTheta = dot(direction, light.direction);
+                let phase = 0.75 * (1.0 + cosTheta * cosTheta);
+                inscattered += light.color * transmittance * phase * density;
+            }
+
+            viewDepth += density * 0.5;
+        }
+
+        color = color * exp(-planet.atmosphere_color * viewDepth) + inscattered * planet.atmosphere_color;
+    }
+    return color;
+}
+
+// Glowing bodies are drawn brighter than the light they give, so they bloom after tone mapping
+const EMISSIVE_INTENSITY: f32 = 4.0;
+
+// Diffuse and specular light from every light source that can see p
+fn shade(index: i32, p: vec3f, normal: vec3f, viewDirection: vec3f, albedo: vec3f, fallback: bool) -> vec3f {
+    let planet = planets.planets[index];
+
+    // Glowing bodies light themselves, a little darker towards the edge
+    if (planet.luminosity > 0.0) {
+        return albedo * planet.luminosity * EMISSIVE_INTENSITY * (0.6 + 0.4 * max(dot(normal, -viewDirection), 0.0));
+    }
+
+    var color = vec3f(0.0);
+    for (var j = 0; j < lightCount(fallback); j++) {
+        let light = getLight(j, p, fallback);
+        let diffuseStrength = dot(light.direction, normal);
+        if (all(light.color == vec3f(0.0)) || diffuseStrength <= 0.0) {
+            continue;
+        }
+        let visibility = lightVisibility(p, light, index);
+        if (visibility <= 0.0) {
+            continue;
+        }
+
+        var lightColor = light.color * visibility;
+        if (hasAtmosphere(planet)) {
+            // Light crossing more air near the terminator loses its blue
+            lightColor *= exp(-planet.atmosphere_color * opticalDepth(planet, p, light.direction));
+        }
+
+        let diffuse = diffuseStrength * albedo;
+        let specularStrength = pow(max(0.0, dot(-viewDirection, reflect(-light.direction, normal))), 64.0);
+        color += lightColor * (diffuse * 0.75 + specularStrength * 0.25);
+    }
+    return color;
+}
+
+// Small bodies are drawn as a dot at least this many pixels wide, so they don't vanish between pixels
+const MIN_DOT_SIZE: f32 = 1.0;
+
+// Draws bodies smaller than a pixel that the ray passes close to, pixelAngle is the width of a pixel in radians
+fn applyDots(origin: vec3f, direction: vec3f, maxDistance: f32, background: vec3f, pixelAngle: f32, fallback: bool) -> vec3f {
+    var color = background;
+    for (var i = 0; i < i32(uniforms.planet_count); i++) {
+        let planet = planets.planets[i];
+        let toCenter = planet.pos - origin;
+        let t = dot(toCenter, direction);
+        // Behind the camera, or behind whatever the ray hit (including this body)
+        if (t <= 0.0 || t >= maxDistance) {
+            continue;
+        }
+
+        let footprint = t * pixelAngle * MIN_DOT_SIZE;
+        let size = planet.radius / footprint;
+        let offset = length(toCenter - direction * t) / footprint;
+        // Fades out as the body grows big enough for the march to find
+        let coverage = (1.0 - smoothstep(0.5, 1.0, offset)) * (1.0 - smoothstep(0.5, 1.0, size));
+        if (coverage <= 0.0) {
+            continue;
+        }
+
+        // Lit like the point of the body facing the camera
+        let p = planet.pos - direction * planet.radius;
+        let surface = surfaceColor(i, p);
+        let dotColor = shade(i, p, -direction, direction, surface.albedo, fallback) + surface.emission;
+        color = mix(color, dotColor, coverage);
+    }
+    return color;
+}
+
+// Where the ray enters and leaves the box, x > y when it misses
+fn rayBox(origin: vec3f, inverseDirection: vec3f, node: BvhNode) -> vec2f {
+    let t0 = (node.min - origin) * inverseDirection;
+    let t1 = (node.max - origin) * inverseDirection;
+    let near = min(t0, t1);
+    let far = max(t0, t1);
+    return vec2f(max(max(near.x, near.y), max(near.z, 0.0)), min(min(far.x, far.y), far.z));
+}
+
+const TERRAIN_STEPS: i32 = 64;
+
+// Distance along the ray to body i, or 1e9 if it misses or is further than maxDistance
+fn intersectBody(i: i32, origin: vec3f, direction: vec3f, maxDistance: f32) -> f32 {
+    let planet = planets.planets[i];
+    let amplitude = planet.radius * max(planet.displacement, 0.0);
+    let bounds = raySphere(origin, direction, planet.pos, planet.radius + amplitude);
+    if (bounds.x > bounds.y || bounds.y < 0.0 || bounds.x >= maxDistance) {
+        return 1e9;
+    }
+    // A camera inside a body sees its surface right away, like the march does
+    let entry = max(bounds.x, 0.0);
+    if (amplitude <= 0.0) {
+        return entry;
+    }
+
+    // Terrain isn't a sphere, march it between where the ray enters and leaves the highest peaks
+    var t = entry;
+    let exit = min(bounds.y, maxDistance);
+    for (var step = 0; step < TERRAIN_STEPS && t < exit; step++) {
+        let distance = planetDistance(i, origin + direction * t);
+        if (distance < 0.001) {
+            return t;
+        }
+        t += distance;
+    }
+    return 1e9;
+}
+
+// The closest body along the ray from the hierarchy, index -1 if there is none
+fn intersectBodies(origin: vec3f, direction: vec3f) -> Hit {
+    var hit = Hit(1e9, -1);
+    let safeDirection = select(direction, vec3f(1e-8), abs(direction) < vec3f(1e-8));
+    let inverseDirection = 1.0 / safeDirection;
+
+    var stack: array<u32, BVH_STACK_SIZE>;
+    stack[0] = 0u;
+    var top = 1u;
+    while (top > 0u) {
+        top--;
+        let index = stack[top];
+        let node = bvh[index];
+        let span = rayBox(origin, inverseDirection, node);
+        if (span.x > span.y || span.x >= hit.distance) {
+            continue;
+        }
+
+        if (node.right == 0u) {
+            let t = intersectBody(i32(node.body), origin, direction, hit.distance);
+            if (t < hit.distance) {
+                hit = Hit(t, i32(node.body));
+            }
+            continue;
+        }
+
+        // The child the ray enters first goes on top
+        let left = index + 1u;
+        let leftFirst = rayBox(origin, inverseDirection, bvh[left]).x <= rayBox(origin, inverseDirection, bvh[node.right]).x;
+        if (top + 2u <= BVH_STACK_SIZE) {
+            stack[top] = select(left, node.right, leftFirst);
+            stack[top + 1u] = select(node.right, left, leftFirst);
+            top += 2u;
+        }
+    }
+    return hit;
+}
+
+// Exact for smooth spheres, terrain still needs the gradient
+fn bodyNormal(index: i32, position: vec3f) -> vec3f {
+    let planet = planets.planets[index];
+    if (planet.displacement <= 0.0) {
+        return normalize(position - planet.pos);
+    }
+    return getNormal(index, position);
+}
+
+// Must match GRAV in calculations.rs
+const GRAV: f32 = 6.67430;
+
+// Beyond this distance from the origin the plane fades out instead of running to the horizon
+const PLANE_EXTENT: f32 = 4000.0;
+
+struct Potential {
+    value: f32,
+    gradient: vec3f,
+}
+
+// Gravitational potential of all bodies at p and its gradient, softened by each radius so wells stay finite
+fn potential(p: vec3f) -> Potential {
+    var result = Potential(0.0, vec3f(0.0));
+    for (var i = 0; i < i32(uniforms.planet_count); i++) {
+        let planet = planets.planets[i];
+        let offset = p - planet.pos;
+        let inverseDistance = inverseSqrt(dot(offset, offset) + planet.radius * planet.radius);
+        result.value -= GRAV * planet.mass * inverseDistance;
+        result.gradient += GRAV * planet.mass * inverseDistance * inverseDistance * inverseDistance * offset;
+    }
+    return result;
+}
+
+// Deep blue far from everything, through purple to orange at the bottom of the deepest wells
+fn potentialColor(well: f32) -> vec3f {
+    let shallow = vec3f(0.03, 0.08, 0.3);
+    let middle = vec3f(0.45, 0.1, 0.55);
+    let deep = vec3f(1.0, 0.55, 0.15);
+    return select(mix(middle, deep, well * 2.0 - 1.0), mix(shallow, middle, well * 2.0), well < 0.5);
+}
+
+// Horizontal plane tinted by the potential with contour lines, seen through where the ray crosses it
+fn applyPotentialPlane(origin: vec3f, direction: vec3f, maxDistance: f32, background: vec3f, pixelAngle: f32) -> vec3f {
+    if (uniforms.potential_plane == 0u || abs(direction.y) < 1e-6) {
+        return background;
+    }
+    let t = (uniforms.plane_height - origin.y) / direction.y;
+    if (t <= 0.0 || t >= maxDistance) {
+        return background;
+    }
+
+    let p = origin + direction * t;
+    let field = potential(p);
+    let depth = -field.value;
+    if (depth <= 0.0) {
+        return background;
+    }
+
+    // Contours are evenly spaced in log potential, so both the star's well and the planets' show
+    let level = log2(depth) * uniforms.contour_density;
+    // How far level moves across one pixel of the plane, lines keep a constant width on screen
+    let footprint = t * pixelAngle / abs(direction.y);
+    let levelPerPixel = max(length(field.gradient) / (depth * log(2.0)) * uniforms.contour_density * footprint, 1e-6);
+    let lineDistance = abs(fract(level + 0.5) - 0.5) / levelPerPixel;
+    // Lines closer together than a couple of pixels fade out instead of flickering
+    let line = (1.0 - smoothstep(0.5, 1.5, lineDistance)) * (1.0 - smoothstep(0.25, 0.5, levelPerPixel));
+
+    let well = clamp((log2(depth) - 3.0) / 8.0, 0.0, 1.0);
+    let fade = 1.0 - smoothstep(PLANE_EXTENT * 0.5, PLANE_EXTENT, length(p.xz));
+    let color = potentialColor(well) + vec3f(0.8, 0.85, 1.0) * line;
+    return mix(background, color, (0.35 + 0.4 * well) * fade);
+}
+
+struct Trace {
+    color: vec3f,
+    // Along the ray to the surface that was hit, 1e9 for the sky
+    distance: f32,
+}
+
+fn trace(rayOrigin: vec3f, rayDirection: vec3f, pixelAngle: f32) -> Trace {
+    var color = vec3(0.0);
+
+    var totalDist = 0.;
+    var outNormal : vec3f;
+
+    var surface : SurfaceColor;
+    var hitIndex = -1;
+    if (uniforms.render_mode == RENDER_MODE_ANALYTIC) {
+        // Straight to the first body along the ray
+        let hit = intersectBodies(rayOrigin, rayDirection);
+        totalDist = hit.distance;
+        if (hit.index >= 0) {
+            let position = rayOrigin + rayDirection * totalDist;
+            outNormal = bodyNormal(hit.index, position);
+            surface = surfaceColor(hit.index, position);
+            hitIndex = hit.index;
+        }
+    } else {
+        // Ray marching
+        for (var i = 0; i < 200; i++){
+            var position : vec3f = rayOrigin + rayDirection * totalDist; // our postion along the ray
+
+            var hit = map(position);
+
+            totalDist += hit.distance;
+
+            if (hit.distance < .001) {
+                // Only the hit point needs a normal and a surface color
+                outNormal = getNormal(hit.index, position);
+                surface = surfaceColor(hit.index, position);
+                hitIndex = hit.index;
+                break;
+            };
+            if (hit.distance > 2000.0 || i+1 == 200) {
+                // let val = mix(0.0,0.05, 1.0 - abs(uv.y)*3.0);
+                /* var val = 0.0;
+                if (uv.y <= 0.0) {
+                    val = 0.03;
+                } */
+                // return vec4f(vec3f(0.0), 1.0);
+                break;
+            }
+        }
+    }
+
+    let fallback = !hasEmissiveBodies();
+    var maxDistance = totalDist;
+    if (hitIndex < 0) {
+        // Dimmed as much as the half alpha sky used to be after blending over the black clear color.
+        // Explicit level since the texture has no mips and u jumps from 1 back to 0 behind the origin
+        color = textureSampleLevel(tex_diffuse, samp_diffuse, sky_uv(rayDirection), 0.0).rgb * 0.25;
+        maxDistance = 1e9;
+    } else {
+        // Coloring and Lighting
+        let p = rayOrigin + rayDirection * totalDist;
+        color = shade(hitIndex, p, outNormal, rayDirection, surface.albedo, fallback);
+
+        // Glowing features aren't lit or shadowed
+        color += surface.emission;
+    }
+
+    color = applyDots(rayOrigin, rayDirection, maxDistance, color, pixelAngle, fallback);
+    color = applyPotentialPlane(rayOrigin, rayDirection, maxDistance, color, pixelAngle);
+    color = applyAtmospheres(rayOrigin, rayDirection, maxDistance, color, fallback);
+    color = applyRings(rayOrigin, rayDirection, maxDistance, color, fallback);
+    return Trace(color, maxDistance);
+}
+
+// Depth range written to the depth buffer, must match camera.rs
+const NEAR: f32 = 0.1;
+const FAR: f32 = 100000.0;
+
+// World to clip space for a camera that casts the same rays as main, so rasterized overlays line up with the
+// scene and can be depth tested against it. RawCameraData::view_projection builds the same matrix
+fn viewProjection(focalLength: f32) -> mat4x4<f32> {
+    let aspect = uniforms.iResolution.x / uniforms.iResolution.y;
+    // The camera axes are the columns of camera.matrix, its transpose takes world directions into view space
+    let rotation = transpose(camera.matrix);
+    let translation = mat4x4<f32>(
+        vec4f(1.0, 0.0, 0.0, 0.0),
+        vec4f(0.0, 1.0, 0.0, 0.0),
+        vec4f(0.0, 0.0, 1.0, 0.0),
+        vec4f(-camera.pos.xyz, 1.0),
+    );
+    // Rays pass through (uv, focalLength) with uv.y from -0.5 to 0.5, view z is forward
+    let projection = mat4x4<f32>(
+        vec4f(2.0 * focalLength / aspect, 0.0, 0.0, 0.0),
+        vec4f(0.0, 2.0 * focalLength, 0.0, 0.0),
+        vec4f(0.0, 0.0, FAR / (FAR - NEAR), 1.0),
+        vec4f(0.0, 0.0, -FAR * NEAR / (FAR - NEAR), 0.0),
+    );
+    return projection * rotation * translation;
+}
+
+struct FragmentOutput {
+    @location(0) color: vec4f,
+    @builtin(frag_depth) depth: f32,
+}
+
+// Where in the pixel sample i goes, a low discrepancy sequence that starts at the center
+fn sampleOffset(i: u32) -> vec2f {
+    return fract(vec2f(0.5) + f32(i) * vec2f(0.7548777, 0.5698403)) - 0.5;
+}
+
 @fragment
-fn main(vertex_output: VertexOutput) -> @location(0) vec4f {
+fn main(vertex_output: VertexOutput) -> FragmentOutput {
     let uv = vertex_output.tex_coords * vec2f(uniforms.iResolution.x / uniforms.iResolution.y, 1.0);
     let m = (uniforms.iMouse.xy * 2 - uniforms.iResolution.xy) / uniforms.iResolution.y;
     let FOV = 100 * (3.14159265 / 180);
@@ -111,11 +860,9 @@ fn main(vertex_output: VertexOutput) -> @location(0) vec4f {
     // Initialization
     var rayOrigin = camera.pos.xyz;
     // var rayOrigin = vec3f(-2.0,0.0,-2.0);
-    var rayDirection = normalize(matrix*vec3f(uv,1/tan(FOV/2)));
-    var color = vec3(0.0);
-
-    var totalDist = 0.;
-    var outNormal : vec3f;
+    let focalLength = 1/tan(FOV/2);
+    // uv spans 1 from the bottom of the screen to the top
+    let pixelSize = 1.0 / uniforms.iResolution.y;
 
     // var xzSwizzle : vec2;
     // rayOrigin.x *= rot2D(-m.x);
@@ -123,57 +870,21 @@ fn main(vertex_output: VertexOutput) -> @location(0) vec4f {
     // rayDirection.x *= rot2D(-m.x);
     // rayDirection.z *= rot2D(-m.x);
 
-    // Ray marching
-    var hit_color = vec3f(0.0);
-    for (var i = 0; i < 200; i++){
-        var position : vec3f = rayOrigin + rayDirection * totalDist; // our postion along the ray
-        var normal : vec3f = getNormal(position);
-
-        var distance = map(position);
-
-        totalDist += distance.w;
-
-        if (distance.w < .001) {
-            outNormal = normal;
-            hit_color = distance.xyz;
-            break;
-        };
-        if (distance.w > 2000.0 || i+1 == 200) {
-            // let val = mix(0.0,0.05, 1.0 - abs(uv.y)*3.0);
-            /* var val = 0.0;
-            if (uv.y <= 0.0) {
-                val = 0.03;
-            } */
-            // return vec4f(vec3f(0.0), 1.0);
-            return textureSample(tex_diffuse, samp_diffuse, vertex_output.tex_coords + vec2f(0.5)) * vec4f(0.5);
-        }
-    }
-
-    // Coloring and Lighting
-    let lightColor = vec3f(1.0); // the color of our light, in this case white
-    let lightSource = vec3f(1.0, 132.0 / 255.0, 0.0);
-    let diffuseStrength = max(0.0, dot(normalize(lightSource), outNormal));
-    let diffuse = lightColor * diffuseStrength * hit_color;
-
-    let viewSource = normalize(rayOrigin);
-    let reflectSource = normalize(reflect(-lightSource, outNormal));
-    var specularStrength = max(0.0, dot(viewSource, reflectSource));
-    specularStrength = pow(specularStrength, 64.0);
-    let specular = specularStrength * lightColor;
-
-    let lighting = diffuse * 0.75 + specular * 0.25;
-    color = lighting;
-
-    // Shadows
-    let p = rayOrigin + rayDirection * totalDist;
-    let lightDirection = normalize(lightSource);
-    let distanceToLightSource = length(lightSource - p);
-    rayOrigin = p * outNormal * 0.1;
-    rayDirection = lightDirection;
-    let shadowDistance = totalDist;
-    if (shadowDistance < distanceToLightSource) {
-        color = color * vec3f(0.25);
-    }
-
-    return vec4(color, 1.0);
+    let samples = max(uniforms.samples, 1u);
+    let viewProj = viewProjection(focalLength);
+    var color = vec3(0.0);
+    // The sky is at the far plane, the nearest surface any sample hits sets the depth
+    var depth = 1.0;
+    for (var i = 0u; i < samples; i++) {
+        let sampleUv = uv + sampleOffset(i) * pixelSize;
+        let rayDirection = normalize(matrix*vec3f(sampleUv, focalLength));
+        let sample = trace(rayOrigin, rayDirection, pixelSize / focalLength);
+        color += sample.color;
+        if (sample.distance < 1e9) {
+            let clip = viewProj * vec4f(rayOrigin + rayDirection * sample.distance, 1.0);
+            depth = min(depth, clamp(clip.z / clip.w, 0.0, 1.0));
+        }
+    }
+
+    return FragmentOutput(vec4(color / f32(samples), 1.0), depth);
 }
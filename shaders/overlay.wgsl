@@ -0,0 +1,32 @@
+// Lines drawn over the ray marched scene, depth tested against what the fragment shader wrote
+
+struct OverlayUniforms {
+    // Same projection as viewProjection in fragment.wgsl
+    view_proj: mat4x4<f32>,
+}
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) color: vec4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> overlay: OverlayUniforms;
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = overlay.view_proj * vec4f(in.position, 1.0);
+    out.color = in.color;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4f {
+    return in.color;
+}
@@ -0,0 +1,91 @@
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+// Must match PostUniforms in postprocess.rs
+struct PostUniforms {
+    exposure: f32,
+    bloom_strength: f32,
+    bloom_threshold: f32,
+    tone_mapping: u32,
+    // One texel along the blur axis, only used by the blur passes
+    blur_direction: vec2<f32>,
+    padding: vec2<f32>,
+}
+
+const TONE_MAPPING_NONE: u32 = 0u;
+const TONE_MAPPING_REINHARD: u32 = 1u;
+const TONE_MAPPING_ACES: u32 = 2u;
+
+@group(0) @binding(0)
+var source: texture_2d<f32>;
+@group(0) @binding(1)
+var linear_sampler: sampler;
+@group(0) @binding(2)
+var<uniform> settings: PostUniforms;
+@group(0) @binding(3)
+var bloom: texture_2d<f32>;
+
+// One triangle that covers the screen, no vertex buffer needed
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let corner = vec2f(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4f(corner * 2.0 - 1.0, 0.0, 1.0);
+    out.uv = vec2f(corner.x, 1.0 - corner.y);
+    return out;
+}
+
+// Keeps only what is brighter than the threshold, drawn into the half size bloom target
+@fragment
+fn bright_pass(in: VertexOutput) -> @location(0) vec4f {
+    let color = textureSampleLevel(source, linear_sampler, in.uv, 0.0).rgb;
+    let brightness = max(color.r, max(color.g, color.b));
+    let contribution = max(brightness - settings.bloom_threshold, 0.0) / max(brightness, 1e-4);
+    return vec4f(color * contribution, 1.0);
+}
+
+// Separable 9 tap gaussian, run once across and once down
+@fragment
+fn blur(in: VertexOutput) -> @location(0) vec4f {
+    var weights = array<f32, 5>(0.227027, 0.1945946, 0.1216216, 0.054054, 0.016216);
+    let texel = settings.blur_direction / vec2f(textureDimensions(source));
+
+    var color = textureSampleLevel(source, linear_sampler, in.uv, 0.0).rgb * weights[0];
+    for (var i = 1; i < 5; i++) {
+        let offset = texel * f32(i);
+        color += textureSampleLevel(source, linear_sampler, in.uv + offset, 0.0).rgb * weights[i];
+        color += textureSampleLevel(source, linear_sampler, in.uv - offset, 0.0).rgb * weights[i];
+    }
+    return vec4f(color, 1.0);
+}
+
+// Fitted curve from Krzysztof Narkowicz, "ACES Filmic Tone Mapping Curve"
+fn aces(x: vec3f) -> vec3f {
+    return clamp((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14), vec3f(0.0), vec3f(1.0));
+}
+
+fn reinhard(x: vec3f) -> vec3f {
+    return x / (1.0 + x);
+}
+
+// Adds the glow and maps the HDR scene into the displayable range
+@fragment
+fn tonemap(in: VertexOutput) -> @location(0) vec4f {
+    let scene = textureSampleLevel(source, linear_sampler, in.uv, 0.0).rgb;
+    let glow = textureSampleLevel(bloom, linear_sampler, in.uv, 0.0).rgb;
+    let color = (scene + glow * settings.bloom_strength) * settings.exposure;
+
+    switch settings.tone_mapping {
+        case TONE_MAPPING_REINHARD: {
+            return vec4f(reinhard(color), 1.0);
+        }
+        case TONE_MAPPING_ACES: {
+            return vec4f(aces(color), 1.0);
+        }
+        default: {
+            return vec4f(clamp(color, vec3f(0.0), vec3f(1.0)), 1.0);
+        }
+    }
+}
@@ -0,0 +1,54 @@
+use image::RgbaImage;
+
+use crate::renderer::RenderMode;
+
+// Renders per mode, the fastest one counts so a stall in one run doesn't skew the result
+const RUNS: usize = 3;
+
+// Channels that differ by at most this much are counted as the same
+const TOLERANCE: u8 = 2;
+
+// How the analytic path compares with ray marching on the same frame
+#[derive(Debug, Clone, Copy)]
+pub struct ModeComparison {
+    pub ray_march_ms: f32,
+    pub analytic_ms: f32,
+    // Average difference per channel, out of 255
+    pub mean_difference: f32,
+    pub max_difference: u8,
+    // Fraction of pixels where some channel differs by more than TOLERANCE
+    pub differing_pixels: f32,
+}
+
+// time_scene draws just the scene with the given mode and returns how long it took in ms, render
+// draws and reads back the whole frame for the comparison, so capturing isn't part of the times
+pub fn compare_render_modes(
+    mut time_scene: impl FnMut(RenderMode) -> f32,
+    mut render: impl FnMut(RenderMode) -> RgbaImage,
+) -> ModeComparison {
+    let mut time = |mode| {
+        let fastest = (0..RUNS).map(|_| time_scene(mode)).fold(f32::MAX, f32::min);
+        (fastest, render(mode))
+    };
+    let (ray_march_ms, ray_marched) = time(RenderMode::RayMarch);
+    let (analytic_ms, analytic) = time(RenderMode::Analytic);
+
+    let mut total = 0u64;
+    let mut max_difference = 0;
+    let mut differing = 0usize;
+    for (a, b) in ray_marched.pixels().zip(analytic.pixels()) {
+        let pixel_max = (0..3).map(|c| a[c].abs_diff(b[c])).max().unwrap_or(0);
+        total += (0..3).map(|c| a[c].abs_diff(b[c]) as u64).sum::<u64>();
+        max_difference = max_difference.max(pixel_max);
+        differing += (pixel_max > TOLERANCE) as usize;
+    }
+    let pixels = (ray_marched.width() * ray_marched.height()).max(1) as f32;
+
+    ModeComparison {
+        ray_march_ms,
+        analytic_ms,
+        mean_difference: total as f32 / (pixels * 3.0),
+        max_difference,
+        differing_pixels: differing as f32 / pixels,
+    }
+}
@@ -0,0 +1,85 @@
+use nalgebra::Vector3;
+
+use crate::calculations::RawPlanetData;
+
+// Deepest the shader's traversal stack can go, a median split over 2^30 bodies stays under this
+pub const MAX_DEPTH: usize = 32;
+
+// One box of the hierarchy, laid out like BvhNode in fragment.wgsl
+// Nodes are stored depth first, so an inner node's left child is the next node
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct BvhNode {
+    pub min: [f32; 3],
+    // Index of the body in a leaf, unused for inner nodes
+    pub body: u32,
+    pub max: [f32; 3],
+    // Index of the right child, 0 for leaves since the root is never a child
+    pub right: u32,
+}
+
+impl BvhNode {
+    pub fn is_leaf(&self) -> bool {
+        self.right == 0
+    }
+
+    // Distance from p to the box, 0 inside it
+    pub fn distance(&self, p: Vector3<f32>) -> f32 {
+        let outside = (Vector3::from(self.min) - p).sup(&(p - Vector3::from(self.max)));
+        outside.sup(&Vector3::zeros()).magnitude()
+    }
+}
+
+// Bounding volume hierarchy over the bodies, rebuilt every frame so the shader only
+// evaluates the bodies near each march step instead of all of them
+pub fn build(planets: &[RawPlanetData]) -> Vec<BvhNode> {
+    let mut nodes = Vec::with_capacity(planets.len() * 2);
+    let mut bodies = (0..planets.len() as u32).collect::<Vec<_>>();
+    if !bodies.is_empty() {
+        build_node(planets, &mut bodies, &mut nodes);
+    }
+    nodes
+}
+
+// Box around everything the surface can reach, terrain included
+fn body_bounds(planet: &RawPlanetData) -> (Vector3<f32>, Vector3<f32>) {
+    let center = Vector3::from(planet.pos);
+    let extent = Vector3::repeat(planet.radius * (1.0 + planet.displacement.max(0.0)));
+    (center - extent, center + extent)
+}
+
+fn build_node(planets: &[RawPlanetData], bodies: &mut [u32], nodes: &mut Vec<BvhNode>) -> usize {
+    let (mut min, mut max) = body_bounds(&planets[bodies[0] as usize]);
+    for &body in bodies.iter() {
+        let (body_min, body_max) = body_bounds(&planets[body as usize]);
+        min = min.inf(&body_min);
+        max = max.sup(&body_max);
+    }
+
+    let index = nodes.len();
+    nodes.push(BvhNode {
+        min: min.into(),
+        body: bodies[0],
+        max: max.into(),
+        right: 0,
+    });
+    if bodies.len() == 1 {
+        return index;
+    }
+
+    // Split at the median along the axis the centers spread furthest on
+    let center = |body: &u32| Vector3::from(planets[*body as usize].pos);
+    let (mut low, mut high) = (center(&bodies[0]), center(&bodies[0]));
+    for body in bodies.iter() {
+        low = low.inf(&center(body));
+        high = high.sup(&center(body));
+    }
+    let axis = (high - low).imax();
+    let middle = bodies.len() / 2;
+    bodies.select_nth_unstable_by(middle, |a, b| center(a)[axis].total_cmp(&center(b)[axis]));
+
+    let (left, right) = bodies.split_at_mut(middle);
+    build_node(planets, left, nodes);
+    nodes[index].right = build_node(planets, right, nodes) as u32;
+    index
+}
@@ -1,10 +1,50 @@
-use nalgebra::Vector3;
+use nalgebra::{UnitQuaternion, Vector3};
+
+use crate::surface::{Atmosphere, Ring, Surface};
 
 // Gravitational constant, can probably adjust
-const GRAV: f32 = 6.67430;
+// fragment.wgsl has a copy for the potential plane
+pub const GRAV: f32 = 6.67430;
+
+// How step_system moves the bodies
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Integrator {
+    // Each body in turn takes a predictor-corrector step against the others' latest positions
+    #[default]
+    Heun,
+    // Kick-drift-kick over the whole system at once, stepping back by -dt retraces a step exactly
+    Verlet,
+}
 
-#[derive(Debug, Clone, Copy)]
+impl Integrator {
+    pub const ALL: [Integrator; 2] = [Integrator::Heun, Integrator::Verlet];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "heun" => Some(Integrator::Heun),
+            "verlet" | "leapfrog" => Some(Integrator::Verlet),
+            _ => None,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Integrator::Heun => "Heun",
+            Integrator::Verlet => "Velocity Verlet",
+        }
+    }
+
+    // Whether running backwards returns to earlier states, collisions aside
+    pub fn reversible(self) -> bool {
+        self == Integrator::Verlet
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Planet {
+    // Shown in the body's label, empty until the scenario or name_unnamed gives it one
+    pub name: String,
+
     // Physical properties
     mass: f32,
     pub pos: Vector3<f32>,
@@ -16,19 +56,39 @@ pub struct Planet {
     // Display properties
     //active: bool,
     radius: f32,
-    //rot_vel: f32, // Angular velocity in rad/s
+    pub rot_vel: f32, // Angular velocity in rad per unit of simulated time
+    // Angle between the spin axis and world up, leaning towards -z
+    pub axial_tilt: f32,
+    // How far the body has turned about its spin axis
+    pub spin_angle: f32,
+    // Index of a body this one always shows the same face to, in place of rot_vel
+    pub tidal_lock: Option<usize>,
     color: [f32; 3],
+    pub surface: Surface,
+    pub atmosphere: Option<Atmosphere>,
+    pub ring: Option<Ring>,
+    // Light given off, 0 for bodies that only reflect light
+    pub luminosity: f32,
 }
 
 impl Default for Planet {
     fn default() -> Self {
         Self {
+            name: String::new(),
             mass: Default::default(),
             pos: Default::default(),
             vel: Default::default(),
             vel2: Default::default(),
             radius: Default::default(),
+            rot_vel: 0.0,
+            axial_tilt: 0.0,
+            spin_angle: 0.0,
+            tidal_lock: None,
             color: [0.5; 3],
+            surface: Default::default(),
+            atmosphere: None,
+            ring: None,
+            luminosity: 0.0,
         }
     }
 }
@@ -40,12 +100,31 @@ pub struct RawPlanetData {
     // pub padding: f32,
     pub radius: f32,
     pub color: [f32; 3],
-    pub padding: f32,
+    // Only used to draw the gravitational potential
+    pub mass: f32,
+    pub surface_type: u32,
+    pub seed: u32,
+    pub feature_scale: f32,
+    pub displacement: f32,
+    pub atmosphere_color: [f32; 3],
+    pub atmosphere_height: f32,
+    pub atmosphere_density: f32,
+    pub luminosity: f32,
+    pub padding_2: [f32; 2],
+    pub ring_normal: [f32; 3],
+    pub ring_inner_radius: f32,
+    pub ring_color: [f32; 3],
+    pub ring_outer_radius: f32,
+    pub ring_opacity: f32,
+    pub padding_3: [f32; 3],
+    // Rotation from the body's frame to the world as a quaternion, x y z w
+    pub orientation: [f32; 4],
 }
 
 impl Planet {
     pub fn new(mass: f32, pos: [f32; 3], radius: f32, color: [f32; 3]) -> Self {
         Planet {
+            name: String::new(),
             mass,
             pos: Vector3::from(pos),
             vel: Default::default(),
@@ -54,10 +133,29 @@ impl Planet {
             //active: true,
             radius,
             color,
-            //rot_vel: f32, // Angular velocity in rad/s
+            rot_vel: 0.0,
+            axial_tilt: 0.0,
+            spin_angle: 0.0,
+            tidal_lock: None,
+            surface: Default::default(),
+            atmosphere: None,
+            ring: None,
+            luminosity: 0.0,
         }
     }
 
+    pub fn mass(&self) -> f32 {
+        self.mass
+    }
+
+    pub fn radius(&self) -> f32 {
+        self.radius
+    }
+
+    pub fn color(&self) -> [f32; 3] {
+        self.color
+    }
+
     // Calculate acceleration
     pub fn calc_accel(&self, planet_list: &mut [&mut Planet]) -> Vector3<f32> {
         // Make sure planet_list doesn't contain self
@@ -92,7 +190,7 @@ impl Planet {
         let vel_old = self.vel;
 
         let accel_old = self.calc_accel(planet_list);
-        self.pos = self.pos + self.vel * dt; // Expected pos
+        self.pos += self.vel * dt; // Expected pos
         let accel_exp = self.calc_accel(planet_list);
         self.vel += (accel_old + accel_exp) * 0.5 * dt; // New vel
         self.pos = pos_old + (self.vel + vel_old) * 0.5 * dt; // New pos
@@ -114,6 +212,32 @@ impl Planet {
         let uv = Vector3::y_axis().cross(&dr.normalize());
 
         self.vel2 = uv * mag;
+        self.vel = self.vel2;
+    }
+
+    fn tilt(&self) -> UnitQuaternion<f32> {
+        UnitQuaternion::from_axis_angle(&Vector3::x_axis(), self.axial_tilt)
+    }
+
+    pub fn spin_axis(&self) -> Vector3<f32> {
+        self.tilt() * Vector3::y()
+    }
+
+    // The tilt followed by the spin so far, body +y is the spin axis
+    pub fn orientation(&self) -> UnitQuaternion<f32> {
+        self.tilt() * UnitQuaternion::from_axis_angle(&Vector3::y_axis(), self.spin_angle)
+    }
+
+    // Turn about the spin axis for dt, negative dt turns back
+    pub fn spin(&mut self, dt: f32) {
+        self.spin_angle = (self.spin_angle + self.rot_vel * dt).rem_euclid(std::f32::consts::TAU);
+    }
+
+    // Turn about the spin axis until body +x points as close to target as it can
+    pub fn face_towards(&mut self, target: Vector3<f32>) {
+        let direction = self.tilt().inverse() * (target - self.pos);
+        // A turn of angle a about y takes +x to (cos a, 0, -sin a)
+        self.spin_angle = (-direction.z).atan2(direction.x);
     }
 
     pub fn to_raw_data(&self) -> RawPlanetData {
@@ -122,7 +246,145 @@ impl Planet {
             // padding: 0.0,
             radius: self.radius,
             color: self.color,
-            padding: 0.0,
+            mass: self.mass,
+            surface_type: self.surface.kind as u32,
+            seed: self.surface.seed,
+            feature_scale: self.surface.feature_scale,
+            displacement: self.surface.displacement,
+            atmosphere_color: self.atmosphere.map_or([0.0; 3], |a| a.color),
+            atmosphere_height: self.atmosphere.map_or(0.0, |a| a.height),
+            atmosphere_density: self.atmosphere.map_or(0.0, |a| a.density),
+            luminosity: self.luminosity,
+            padding_2: [0.0; 2],
+            ring_normal: self.ring.map_or([0.0, 1.0, 0.0], |r| r.normal.into()),
+            ring_inner_radius: self.ring.map_or(0.0, |r| r.inner_radius * self.radius),
+            ring_color: self.ring.map_or([0.0; 3], |r| r.color),
+            ring_outer_radius: self.ring.map_or(0.0, |r| r.outer_radius * self.radius),
+            ring_opacity: self.ring.map_or(0.0, |r| r.opacity),
+            padding_3: [0.0; 3],
+            orientation: self.orientation().coords.into(),
         }
     }
 }
+
+// Split the list into the planet at index and references to every other planet
+pub fn splice_planets(index: usize, planets: &mut [Planet]) -> (&mut Planet, Vec<&mut Planet>) {
+    let (pre_planets, post_planets_and_current) = planets.split_at_mut(index);
+    let (this_planet, post_planets) = post_planets_and_current.split_first_mut().unwrap();
+    let chain = pre_planets.iter_mut().chain(post_planets.iter_mut());
+    (this_planet, chain.collect::<Vec<_>>())
+}
+
+// Change every velocity by the acceleration at the current positions over dt
+fn kick(planets: &mut [Planet], dt: f32) {
+    let accelerations = (0..planets.len())
+        .map(|i| {
+            let (this_planet, mut other_planets) = splice_planets(i, planets);
+            this_planet.calc_accel(&mut other_planets)
+        })
+        .collect::<Vec<_>>();
+    for (planet, accel) in planets.iter_mut().zip(accelerations) {
+        planet.vel += accel * dt;
+    }
+}
+
+// Advance every planet by one physics step of dt, negative dt runs the system backwards
+pub fn step_system(planets: &mut [Planet], dt: f32, integrator: Integrator) {
+    // Calculate any collisions
+    for i in 0..planets.len() {
+        let (this_planet, mut other_planets) = splice_planets(i, planets);
+
+        this_planet.calc_collision(&mut other_planets);
+    }
+
+    match integrator {
+        Integrator::Heun => {
+            // Frame update for physics
+            for i in 0..planets.len() {
+                let (this_planet, mut other_planets) = splice_planets(i, planets);
+
+                this_planet.step(&mut other_planets, dt);
+            }
+        }
+        Integrator::Verlet => {
+            for planet in planets.iter_mut() {
+                planet.vel = planet.vel2;
+            }
+            kick(planets, dt * 0.5);
+            for planet in planets.iter_mut() {
+                planet.pos += planet.vel * dt;
+            }
+            kick(planets, dt * 0.5);
+            for planet in planets.iter_mut() {
+                planet.vel2 = planet.vel;
+            }
+        }
+    }
+
+    // Spin, tidally locked bodies turn to face what they're locked to instead
+    for i in 0..planets.len() {
+        let lock_target = planets[i]
+            .tidal_lock
+            .filter(|&target| target != i)
+            .and_then(|target| planets.get(target))
+            .map(|target| target.pos);
+
+        match lock_target {
+            Some(target) => planets[i].face_towards(target),
+            None => planets[i].spin(dt),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A star with two planets in circular orbits, far enough apart not to collide
+    fn system() -> Vec<Planet> {
+        let mut planets = vec![
+            Planet::new(10000.0, [0.0; 3], 35.0, [1.0; 3]),
+            Planet::new(10.0, [100.0, 0.0, 0.0], 5.0, [1.0; 3]),
+            Planet::new(15.0, [0.0, 0.0, -250.0], 8.0, [1.0; 3]),
+        ];
+        for i in 1..planets.len() {
+            let (this_planet, mut other_planets) = splice_planets(i, &mut planets);
+            this_planet.set_init_velocity(&mut other_planets);
+        }
+        planets
+    }
+
+    // Largest position and velocity difference after running forwards and back again
+    fn round_trip_error(integrator: Integrator) -> (f32, f32) {
+        let start = system();
+        let mut planets = start.clone();
+        // About two orbits of the inner planet
+        let (steps, dt) = (5000, 0.01);
+        for _ in 0..steps {
+            step_system(&mut planets, dt, integrator);
+        }
+        assert!((planets[1].pos - start[1].pos).norm() > 1.0);
+        for _ in 0..steps {
+            step_system(&mut planets, -dt, integrator);
+        }
+
+        let mut errors = (0.0_f32, 0.0_f32);
+        for (planet, start) in planets.iter().zip(&start) {
+            errors.0 = errors.0.max((planet.pos - start.pos).norm());
+            errors.1 = errors.1.max((planet.vel - start.vel).norm());
+        }
+        errors
+    }
+
+    // Verlet retraces exactly but for 32-bit rounding, which leaves about 1e-5 of the orbit radius
+    // and speed after 10000 steps. Heun's one-body-at-a-time step comes back tens of times further off
+    #[test]
+    fn verlet_retraces_its_steps() {
+        let (pos_error, vel_error) = round_trip_error(Integrator::Verlet);
+        assert!(pos_error < 5e-3, "position off by {pos_error}");
+        assert!(vel_error < 1e-3, "velocity off by {vel_error}");
+
+        let (pos_error, vel_error) = round_trip_error(Integrator::Heun);
+        assert!(pos_error > 5e-3 && vel_error > 1e-3);
+    }
+}
@@ -1,43 +1,147 @@
-use nalgebra::{Matrix4, Vector3};
+use nalgebra::{Matrix4, UnitQuaternion, Vector3};
+use winit::keyboard::KeyCode;
+
+// Depth range of the projection fragment.wgsl writes depth with
+pub const NEAR: f32 = 0.1;
+pub const FAR: f32 = 100000.0;
+// Field of view the shader casts rays with, across 1 / tan(FOV / 2) of the screen height
+const FOV: f32 = 100.0 * (std::f32::consts::PI / 180.0);
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CameraMode {
+    // Rotates around the origin, controlled by the euler angles
+    #[default]
+    Orbit,
+    // Moves freely with WASD/QE, orientation stored as a quaternion
+    FreeFly,
+}
+
+// Movement keys currently held down in free-fly mode
+#[derive(Debug, Default, Clone, Copy)]
+struct MovementKeys {
+    forward: bool,
+    back: bool,
+    left: bool,
+    right: bool,
+    up: bool,
+    down: bool,
+}
 
 #[derive(Debug, Default)]
 pub struct Camera {
+    pub mode: CameraMode,
+
     pub pos: Vector3<f32>,
     // Angles of rotation
     pub roll: f32, // Unchanged
     pub pitch: f32,
     pub yaw: f32,
 
+    // Free-fly state, in world space
+    pub fly_pos: Vector3<f32>,
+    pub orientation: UnitQuaternion<f32>,
+    pub fly_speed: f32, // Units per second
+    movement: MovementKeys,
+
     // Sensitivity settings
     pitch_sens: f32,
     roll_sens: f32,
+    look_sens: f32,
 }
 
 impl Camera {
+    // The starting view, orbiting the origin from slightly above
+    pub fn starting_view() -> Self {
+        let mut camera = Self {
+            pos: Vector3::new(0.0, 0.0, -200.0),
+            roll: std::f32::consts::FRAC_PI_6,
+            fly_speed: 100.0,
+            ..Default::default()
+        };
+        camera.set_sensitivity([-1.0, 1.0]);
+        camera
+    }
+
     fn get_rotation_matrix(&self) -> Matrix4<f32> {
         Matrix4::from_euler_angles(self.roll, self.pitch, self.yaw)
     }
 
     pub fn to_raw_data(&self) -> RawCameraData {
-        let rotation_matrix = self.get_rotation_matrix();
+        match self.mode {
+            CameraMode::Orbit => {
+                let rotation_matrix = self.get_rotation_matrix();
 
-        // println!("Pos: {}", self.pos);
+                // println!("Pos: {}", self.pos);
 
-        RawCameraData {
-            pos: (rotation_matrix * self.pos.to_homogeneous()).into(),
-            matrix: rotation_matrix.into(),
-            /* pos: self.pos.into(),
-            padding: [0.0; 4], */
+                RawCameraData {
+                    pos: (rotation_matrix * self.pos.to_homogeneous()).into(),
+                    matrix: rotation_matrix.into(),
+                    /* pos: self.pos.into(),
+                    padding: [0.0; 4], */
+                }
+            }
+            CameraMode::FreeFly => RawCameraData {
+                pos: self.fly_pos.push(1.0).into(),
+                matrix: self.orientation.to_homogeneous().into(),
+            },
         }
     }
 
+    // Switch between orbit and free-fly, starting free-fly from the current orbit view
+    pub fn toggle_mode(&mut self) {
+        self.mode = match self.mode {
+            CameraMode::Orbit => {
+                self.orientation =
+                    UnitQuaternion::from_euler_angles(self.roll, self.pitch, self.yaw);
+                self.fly_pos = self.orientation * self.pos;
+                CameraMode::FreeFly
+            }
+            CameraMode::FreeFly => CameraMode::Orbit,
+        };
+        self.movement = MovementKeys::default();
+    }
+
+    // Switch to free-fly and place the camera at pos, looking at target
+    pub fn look_at(&mut self, pos: Vector3<f32>, target: Vector3<f32>) {
+        let direction = target - pos;
+        if direction.magnitude_squared() > 0.0 {
+            // Pick another up vector when looking straight up or down
+            let up = if direction.normalize().y.abs() > 0.999 {
+                Vector3::z()
+            } else {
+                Vector3::y()
+            };
+            self.orientation = UnitQuaternion::face_towards(&direction, &up);
+        }
+
+        self.mode = CameraMode::FreeFly;
+        self.fly_pos = pos;
+    }
+
     // Update angles of rotation from dpos[x, y] of mouse
     pub fn rotate_from_mouse(&mut self, dpos: [f64; 2]) {
-        self.pitch -= (dpos[0] as f32) * self.pitch_sens;
-        // self.pitch = std::f32::consts::PI;
-        // self.yaw += (dpos[0] as f32) * self.yaw_sens;
-        self.roll += (dpos[1] as f32) * self.roll_sens;
-        // println!("rot: {}", self.get_rotation_matrix());
+        match self.mode {
+            CameraMode::Orbit => {
+                self.pitch -= (dpos[0] as f32) * self.pitch_sens;
+                // self.pitch = std::f32::consts::PI;
+                // self.yaw += (dpos[0] as f32) * self.yaw_sens;
+                self.roll += (dpos[1] as f32) * self.roll_sens;
+                // println!("rot: {}", self.get_rotation_matrix());
+            }
+            CameraMode::FreeFly => {
+                // Yaw around the world up axis so the horizon stays level, pitch around the local x axis
+                let yaw = UnitQuaternion::from_axis_angle(
+                    &Vector3::y_axis(),
+                    dpos[0] as f32 * self.look_sens,
+                );
+                let pitch = UnitQuaternion::from_axis_angle(
+                    &Vector3::x_axis(),
+                    dpos[1] as f32 * self.look_sens,
+                );
+                self.orientation = yaw * self.orientation * pitch;
+                self.orientation.renormalize();
+            }
+        }
     }
 
     // Set sensitivity from argument [pitch, yaw]
@@ -45,16 +149,59 @@ impl Camera {
     pub fn set_sensitivity(&mut self, set: [f32; 2]) {
         self.pitch_sens = set[0] / 100.0;
         self.roll_sens = set[1] / 100.0;
+        self.look_sens = set[1].abs() / 200.0;
     }
 
     pub fn scroll(&mut self, delta: f32) {
-        let delta = 1.0 + delta;
-        if delta > 1.0 && self.pos.magnitude_squared() < 4000000.0 {
-            self.pos *= delta;
+        match self.mode {
+            CameraMode::Orbit => {
+                let delta = 1.0 + delta;
+                if delta > 1.0 && self.pos.magnitude_squared() < 4000000.0 {
+                    self.pos *= delta;
+                }
+
+                if delta < 1.0 && self.pos.magnitude_squared() > 0.01 {
+                    self.pos *= delta;
+                }
+            }
+            // Scrolling adjusts the flying speed instead of zooming
+            CameraMode::FreeFly => {
+                self.fly_speed = (self.fly_speed * 1.1_f32.powf(delta)).clamp(1.0, 10000.0);
+            }
+        }
+    }
+
+    // Track held movement keys, returns true if the key was used by the camera
+    pub fn process_key(&mut self, key: KeyCode, pressed: bool) -> bool {
+        let held = match key {
+            KeyCode::KeyW => &mut self.movement.forward,
+            KeyCode::KeyS => &mut self.movement.back,
+            KeyCode::KeyA => &mut self.movement.left,
+            KeyCode::KeyD => &mut self.movement.right,
+            KeyCode::KeyE => &mut self.movement.up,
+            KeyCode::KeyQ => &mut self.movement.down,
+            _ => return false,
+        };
+        *held = pressed;
+        true
+    }
+
+    // Move the free-fly camera along its local axes, dt in seconds
+    pub fn update(&mut self, dt: f32) {
+        if self.mode != CameraMode::FreeFly {
+            return;
         }
 
-        if delta < 1.0 && self.pos.magnitude_squared() > 0.01 {
-            self.pos *= delta;
+        let keys = self.movement;
+        let axis = |positive: bool, negative: bool| positive as i32 as f32 - negative as i32 as f32;
+        let offset = Vector3::new(
+            axis(keys.right, keys.left),
+            axis(keys.up, keys.down),
+            axis(keys.forward, keys.back),
+        );
+
+        if offset != Vector3::zeros() {
+            self.fly_pos += self.orientation * offset.normalize() * self.fly_speed * dt;
         }
     }
 }
@@ -62,8 +209,8 @@ impl Camera {
 #[repr(C)]
 #[derive(Clone, Copy, bytemuck::Zeroable, bytemuck::Pod)]
 pub struct RawCameraData {
-    pos: [f32; 4],
-    matrix: [[f32; 4]; 4],
+    pub pos: [f32; 4],
+    pub matrix: [[f32; 4]; 4],
     /* a: [f32;3],
     b: [f32;3],
     c: [f32;3], */
@@ -71,3 +218,65 @@ pub struct RawCameraData {
     /* pos: [f32; 3],
     padding: [f32; 4], */
 }
+
+impl RawCameraData {
+    pub fn position(&self) -> Vector3<f32> {
+        Vector3::new(self.pos[0], self.pos[1], self.pos[2])
+    }
+
+    // How far ahead of the camera the plane through the origin is, measured along the view direction.
+    // The orbit camera always looks at the origin, a free-fly camera can have it behind, giving None
+    pub fn origin_depth(&self) -> Option<f32> {
+        let [x, y, z, _] = self.matrix[2];
+        let depth = -self.position().dot(&Vector3::new(x, y, z));
+        (depth > NEAR).then_some(depth)
+    }
+
+    // World units covered by one pixel of a screen height pixels tall, at distance from the camera
+    pub fn units_per_pixel(distance: f32, height: f32) -> f32 {
+        distance * (FOV / 2.0).tan() / height
+    }
+
+    // World to clip space for the rays fragment.wgsl casts, for drawing lines on top of the scene
+    // Mirrors viewProjection in the shader so both agree on depth
+    pub fn view_projection(&self, aspect: f32) -> Matrix4<f32> {
+        let focal_length = 1.0 / (FOV / 2.0).tan();
+        // The camera axes are the columns of matrix
+        let rotation = Matrix4::from(self.matrix).transpose();
+        let translation = Matrix4::new_translation(&-self.position());
+        #[rustfmt::skip]
+        let projection = Matrix4::new(
+            2.0 * focal_length / aspect, 0.0, 0.0, 0.0,
+            0.0, 2.0 * focal_length, 0.0, 0.0,
+            0.0, 0.0, FAR / (FAR - NEAR), -FAR * NEAR / (FAR - NEAR),
+            0.0, 0.0, 1.0, 0.0,
+        );
+        projection * rotation * translation
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn origin_depth_follows_the_view() {
+        let mut camera = Camera::starting_view();
+        let depth = camera.to_raw_data().origin_depth().unwrap();
+        assert!((depth - 200.0).abs() < 1e-3, "{depth}");
+
+        // Looking past the origin from the side only counts the distance along the view
+        camera.look_at(
+            Vector3::new(-50.0, 0.0, -100.0),
+            Vector3::new(-50.0, 0.0, 0.0),
+        );
+        let depth = camera.to_raw_data().origin_depth().unwrap();
+        assert!((depth - 100.0).abs() < 1e-3, "{depth}");
+
+        camera.look_at(
+            Vector3::new(0.0, 0.0, -100.0),
+            Vector3::new(0.0, 0.0, -200.0),
+        );
+        assert!(camera.to_raw_data().origin_depth().is_none());
+    }
+}
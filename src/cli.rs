@@ -0,0 +1,321 @@
+use std::path::PathBuf;
+
+use crate::{
+    calculations::Integrator,
+    clock::TIME_SCALE,
+    postprocess::{PostSettings, ToneMapping},
+    recorder::RecordFormat,
+    renderer::{PotentialPlane, RenderMode},
+    scenario::Scenario,
+    units::{Quantity, Units},
+};
+
+// Upper limit for --samples and the Display window, every sample is a full ray march
+pub const MAX_SAMPLES: u32 = 16;
+
+pub const USAGE: &str = "\
+Usage: celestial_simulator [options]
+
+Options:
+  --scenario <name>          System to simulate: random, solar-system (default random)
+  --bodies <count>           Number of bodies in the random system, star included (default 5)
+  --horizons <file>          Start from a JPL Horizons VECTORS table instead of the scenario, repeat for
+                             more bodies
+  --headless                 Run the simulation without a window
+  --units <system>           Units for times shown, read from the command line and recorded:
+                             simulation, si, astronomical, nbody (default simulation)
+  --duration <time>          Simulated time to run for in headless mode (default 1000 time units)
+  --integrator <name>        Physics step: heun, verlet (default heun). Time can only run backwards with verlet
+  --dt <step>                Physics step used in headless mode (default: the scenario's longest step)
+  --thumbnail <file>         Save a PNG of the final state in headless mode, rendered on the CPU
+  --thumbnail-size <WxH>     Resolution of the thumbnail (default 480x270)
+  --record <file>            Record trajectories to a .csv or .jsonl file
+  --record-format <format>   Override the recording format (csv, jsonl)
+  --record-interval <time>   Simulated time between recorded samples (default 1 time unit)
+  --sky <image>              Equirectangular background image to use instead of the bundled starfield
+  --render-mode <mode>       How rays find the bodies: march, analytic (default march)
+  --potential                Show the plane colored by gravitational potential
+  --samples <count>          Rays per pixel for anti-aliasing, 1 to 16 (default 2)
+  --exposure <factor>        Brightness multiplier applied before tone mapping (default 1)
+  --tone-mapping <curve>     Tone mapping curve: none, reinhard, aces (default aces)
+  --replay <file>            Play back a recorded .csv or .jsonl file instead of simulating
+  --screenshot-size <WxH>    Output size for supersampled screenshots (default: window size)
+  --supersample <factor>     Supersampling factor for Shift+F12 screenshots and exports (default 2)
+  --export <dir>             Render an animation to numbered PNG frames in a directory, then exit
+  --frames <count>           Number of frames to export (default 300)
+  --fps <rate>               Frame rate of the exported animation (default 30)
+  --export-size <WxH>        Resolution of exported frames (default 1920x1080)
+  --frame-dt <time>          Simulated time between exported frames (default: real time at 1x speed)
+  --keyframes <file>         Camera keyframes for the export, one per line:
+                             time x y z target_x target_y target_z
+  --kepler-report            Compare two-body runs with Kepler's solution, print the errors and exit
+  -h, --help                 Print this message";
+
+#[derive(Debug, Clone)]
+pub struct Args {
+    pub scenario: Scenario,
+    pub bodies: usize,
+    // Horizons tables to build the system from in place of the scenario
+    pub horizons: Vec<PathBuf>,
+    pub headless: bool,
+    pub units: Units,
+    // Times are kept in simulation units, --units only changes how they're given
+    pub duration: f64,
+    pub dt: Option<f32>,
+    pub integrator: Integrator,
+    pub thumbnail: Option<PathBuf>,
+    pub thumbnail_size: [u32; 2],
+    pub record: Option<PathBuf>,
+    pub record_format: Option<RecordFormat>,
+    pub record_interval: f64,
+    pub sky: Option<PathBuf>,
+    pub render_mode: RenderMode,
+    pub potential: bool,
+    pub samples: u32,
+    pub exposure: f32,
+    pub tone_mapping: ToneMapping,
+    pub replay: Option<PathBuf>,
+    pub screenshot_size: Option<[u32; 2]>,
+    pub supersample: u32,
+    pub export: Option<PathBuf>,
+    pub frames: u32,
+    pub fps: f32,
+    pub export_size: [u32; 2],
+    pub frame_dt: Option<f32>,
+    pub keyframes: Option<PathBuf>,
+    pub kepler_report: bool,
+}
+
+impl Default for Args {
+    fn default() -> Self {
+        Self {
+            scenario: Scenario::default(),
+            bodies: 5,
+            horizons: Vec::new(),
+            headless: false,
+            units: Units::default(),
+            duration: 1000.0,
+            dt: None,
+            integrator: Integrator::default(),
+            thumbnail: None,
+            thumbnail_size: [480, 270],
+            record: None,
+            record_format: None,
+            record_interval: 1.0,
+            sky: None,
+            render_mode: RenderMode::default(),
+            potential: false,
+            samples: 2,
+            exposure: 1.0,
+            tone_mapping: ToneMapping::default(),
+            replay: None,
+            screenshot_size: None,
+            supersample: 2,
+            export: None,
+            frames: 300,
+            fps: 30.0,
+            export_size: [1920, 1080],
+            frame_dt: None,
+            keyframes: None,
+            kepler_report: false,
+        }
+    }
+}
+
+impl Args {
+    // Parse the process arguments, returns Ok(None) if help was requested
+    pub fn parse() -> Result<Option<Self>, String> {
+        Self::parse_from(std::env::args().skip(1))
+    }
+
+    pub fn parse_from(arguments: impl IntoIterator<Item = String>) -> Result<Option<Self>, String> {
+        let mut args = Self::default();
+        let mut arguments = arguments.into_iter();
+        // Converted once --units is known, it can come after them
+        let (mut duration, mut dt, mut record_interval, mut frame_dt) = (None, None, None, None);
+
+        while let Some(argument) = arguments.next() {
+            let mut value = || {
+                arguments
+                    .next()
+                    .ok_or_else(|| format!("Missing value for {argument}"))
+            };
+
+            match argument.as_str() {
+                "--scenario" => {
+                    let name = value()?;
+                    args.scenario = Scenario::from_name(&name)
+                        .ok_or_else(|| format!("Unknown scenario: {name}"))?;
+                }
+                "--bodies" => args.bodies = parse_number(&argument, &value()?)?,
+                "--horizons" => args.horizons.push(PathBuf::from(value()?)),
+                "--headless" => args.headless = true,
+                "--units" => {
+                    let name = value()?;
+                    args.units =
+                        Units::from_name(&name).ok_or_else(|| format!("Unknown units: {name}"))?;
+                }
+                "--duration" => duration = Some(parse_number(&argument, &value()?)?),
+                "--dt" => dt = Some(parse_number(&argument, &value()?)?),
+                "--integrator" => {
+                    let name = value()?;
+                    args.integrator = Integrator::from_name(&name)
+                        .ok_or_else(|| format!("Unknown integrator: {name}"))?;
+                }
+                "--thumbnail" => args.thumbnail = Some(PathBuf::from(value()?)),
+                "--thumbnail-size" => args.thumbnail_size = parse_size(&argument, &value()?)?,
+                "--record" => args.record = Some(PathBuf::from(value()?)),
+                "--record-format" => {
+                    let name = value()?;
+                    args.record_format = Some(
+                        RecordFormat::from_name(&name)
+                            .ok_or_else(|| format!("Unknown recording format: {name}"))?,
+                    );
+                }
+                "--record-interval" => record_interval = Some(parse_number(&argument, &value()?)?),
+                "--sky" => args.sky = Some(PathBuf::from(value()?)),
+                "--render-mode" => {
+                    let name = value()?;
+                    args.render_mode = RenderMode::from_name(&name)
+                        .ok_or_else(|| format!("Unknown render mode: {name}"))?;
+                }
+                "--potential" => args.potential = true,
+                "--samples" => args.samples = parse_number(&argument, &value()?)?,
+                "--exposure" => args.exposure = parse_number(&argument, &value()?)?,
+                "--tone-mapping" => {
+                    let name = value()?;
+                    args.tone_mapping = ToneMapping::from_name(&name)
+                        .ok_or_else(|| format!("Unknown tone mapping: {name}"))?;
+                }
+                "--replay" => args.replay = Some(PathBuf::from(value()?)),
+                "--screenshot-size" => {
+                    args.screenshot_size = Some(parse_size(&argument, &value()?)?)
+                }
+                "--supersample" => args.supersample = parse_number(&argument, &value()?)?,
+                "--export" => args.export = Some(PathBuf::from(value()?)),
+                "--frames" => args.frames = parse_number(&argument, &value()?)?,
+                "--fps" => args.fps = parse_number(&argument, &value()?)?,
+                "--export-size" => args.export_size = parse_size(&argument, &value()?)?,
+                "--frame-dt" => frame_dt = Some(parse_number(&argument, &value()?)?),
+                "--keyframes" => args.keyframes = Some(PathBuf::from(value()?)),
+                "--kepler-report" => args.kepler_report = true,
+                "-h" | "--help" => return Ok(None),
+                _ => return Err(format!("Unknown argument: {argument}")),
+            }
+        }
+
+        let units = args.units;
+        let time = |value: f64| units.to_sim(value, Quantity::Time);
+        if let Some(duration) = duration {
+            args.duration = time(duration);
+        }
+        if let Some(dt) = dt {
+            args.dt = Some(time(dt) as f32);
+        }
+        if let Some(record_interval) = record_interval {
+            args.record_interval = time(record_interval);
+        }
+        if let Some(frame_dt) = frame_dt {
+            args.frame_dt = Some(time(frame_dt) as f32);
+        }
+
+        if args.headless && args.replay.is_some() {
+            return Err("--replay needs a window and can't be used with --headless".to_string());
+        }
+
+        if args.headless && args.export.is_some() {
+            return Err(
+                "--export renders on the GPU and can't be used with --headless".to_string(),
+            );
+        }
+
+        if args.bodies == 0 {
+            return Err("--bodies must be at least 1".to_string());
+        }
+
+        if args.fps <= 0.0 {
+            return Err("--fps must be positive".to_string());
+        }
+
+        if args.supersample == 0 {
+            return Err("--supersample must be at least 1".to_string());
+        }
+
+        if args.dt.is_some_and(|dt| dt <= 0.0) {
+            return Err("--dt must be positive".to_string());
+        }
+
+        if args.frame_dt.is_some_and(|frame_dt| frame_dt <= 0.0) {
+            return Err("--frame-dt must be positive".to_string());
+        }
+
+        if !(1..=MAX_SAMPLES).contains(&args.samples) {
+            return Err(format!("--samples must be between 1 and {MAX_SAMPLES}"));
+        }
+
+        if args.exposure <= 0.0 {
+            return Err("--exposure must be positive".to_string());
+        }
+
+        Ok(Some(args))
+    }
+
+    // Longest physics step for the bodies being simulated
+    pub fn max_step(&self) -> f32 {
+        if self.horizons.is_empty() {
+            self.scenario.max_step()
+        } else {
+            // Tables are for real bodies, which may include moons
+            Scenario::SolarSystem.max_step()
+        }
+    }
+
+    // Physics step for headless runs
+    pub fn dt(&self) -> f32 {
+        self.dt.unwrap_or(self.max_step())
+    }
+
+    // Simulated time per exported frame, defaults to what 1x speed shows in the same time
+    pub fn frame_dt(&self) -> f32 {
+        self.frame_dt.unwrap_or(TIME_SCALE / self.fps)
+    }
+
+    // Display settings from --exposure and --tone-mapping, the rest stay at their defaults
+    pub fn post_settings(&self) -> PostSettings {
+        PostSettings {
+            tone_mapping: self.tone_mapping,
+            exposure: self.exposure,
+            ..Default::default()
+        }
+    }
+
+    pub fn potential_plane(&self) -> PotentialPlane {
+        PotentialPlane {
+            visible: self.potential,
+            ..Default::default()
+        }
+    }
+
+    // The format for --record, from --record-format or the file extension
+    pub fn record_format(&self) -> RecordFormat {
+        self.record_format
+            .or_else(|| self.record.as_deref().and_then(RecordFormat::from_path))
+            .unwrap_or_default()
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(argument: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value for {argument}: {value}"))
+}
+
+// Parse a resolution written as WIDTHxHEIGHT
+fn parse_size(argument: &str, value: &str) -> Result<[u32; 2], String> {
+    let invalid = || format!("Invalid value for {argument}, expected WIDTHxHEIGHT: {value}");
+    let (width, height) = value.split_once(['x', 'X']).ok_or_else(invalid)?;
+    match (width.trim().parse(), height.trim().parse()) {
+        (Ok(width), Ok(height)) if width > 0 && height > 0 => Ok([width, height]),
+        _ => Err(invalid()),
+    }
+}
@@ -0,0 +1,166 @@
+use winit::keyboard::KeyCode;
+
+use crate::calculations::Integrator;
+
+pub const MIN_SPEED: f32 = 0.01;
+pub const MAX_SPEED: f32 = 10000.0;
+
+// Simulated time units that pass per second of wall-clock time at 1x speed
+pub const TIME_SCALE: f32 = 10.0;
+// Default longest physics step, higher speeds run more steps instead of a larger dt
+pub const MAX_SUBSTEP: f32 = 0.2;
+// Upper bound on physics steps per frame so a slow frame can't stall the app
+const MAX_SUBSTEPS_PER_FRAME: u32 = 50000;
+// Wall-clock frame time is clamped so a hitch (e.g. dragging the window) doesn't jump the simulation
+const MAX_FRAME_TIME: f32 = 0.25;
+
+// The physics steps to run for one frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Substeps {
+    pub count: u32,
+    pub dt: f32, // Negative when the clock runs in reverse
+}
+
+#[derive(Debug)]
+pub struct SimulationClock {
+    pub paused: bool,
+    pub speed: f32,
+    // Only takes effect with a reversible integrator, see runs_backwards
+    pub reversed: bool,
+    pub integrator: Integrator,
+    // Total simulated time elapsed, updated as each substep is run
+    pub time: f64,
+    // Longest physics step, set by the scenario
+    pub max_step: f32,
+    // Speed the last frame ran at, below speed when it needed more than MAX_SUBSTEPS_PER_FRAME steps
+    pub achieved_speed: f32,
+    pending_steps: u32,
+}
+
+impl Default for SimulationClock {
+    fn default() -> Self {
+        Self {
+            paused: false,
+            speed: 1.0,
+            reversed: false,
+            integrator: Integrator::default(),
+            time: 0.0,
+            max_step: MAX_SUBSTEP,
+            achieved_speed: 1.0,
+            pending_steps: 0,
+        }
+    }
+}
+
+impl SimulationClock {
+    // Work out the physics steps for a frame that took frame_time seconds
+    pub fn advance(&mut self, frame_time: f32) -> Substeps {
+        let direction = if self.runs_backwards() { -1.0 } else { 1.0 };
+
+        if self.paused {
+            // Single steps always use the full step size so they are visible
+            let count = std::mem::take(&mut self.pending_steps);
+            Substeps {
+                count,
+                dt: self.max_step * direction,
+            }
+        } else {
+            let sim_time = frame_time.min(MAX_FRAME_TIME) * TIME_SCALE * self.speed;
+            let count = ((sim_time / self.max_step).ceil() as u32).clamp(1, MAX_SUBSTEPS_PER_FRAME);
+            // Past the limit the steps stay at max_step and the clock falls behind instead
+            let dt = (sim_time / count as f32).min(self.max_step);
+            self.achieved_speed = if sim_time > 0.0 {
+                self.speed * (dt * count as f32 / sim_time).min(1.0)
+            } else {
+                self.speed
+            };
+            Substeps {
+                count,
+                dt: dt * direction,
+            }
+        }
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+        self.pending_steps = 0;
+    }
+
+    // Queue one physics step, pauses the clock if it was running
+    pub fn single_step(&mut self) {
+        self.paused = true;
+        self.pending_steps += 1;
+    }
+
+    // Stepping a non-reversible integrator backwards wouldn't retrace the run, so it only goes forwards
+    pub fn runs_backwards(&self) -> bool {
+        self.reversed && self.integrator.reversible()
+    }
+
+    // Whether the step limit kept the last frame from reaching the set speed
+    pub fn is_limited(&self) -> bool {
+        self.achieved_speed < self.speed * 0.999
+    }
+
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = speed.clamp(MIN_SPEED, MAX_SPEED);
+    }
+
+    // Handle a key press, returns true if the key was used by the clock
+    pub fn process_key(&mut self, key: KeyCode) -> bool {
+        match key {
+            KeyCode::Space => self.toggle_pause(),
+            KeyCode::Period => self.single_step(),
+            KeyCode::Equal | KeyCode::NumpadAdd => self.set_speed(self.speed * 2.0),
+            KeyCode::Minus | KeyCode::NumpadSubtract => self.set_speed(self.speed / 2.0),
+            KeyCode::KeyR if self.integrator.reversible() => self.reversed = !self.reversed,
+            _ => return false,
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_reversible_integrators_run_backwards() {
+        let mut clock = SimulationClock {
+            integrator: Integrator::Heun,
+            ..Default::default()
+        };
+        clock.process_key(KeyCode::KeyR);
+        assert!(!clock.runs_backwards());
+        assert!(clock.advance(0.016).dt > 0.0);
+
+        clock.integrator = Integrator::Verlet;
+        clock.process_key(KeyCode::KeyR);
+        assert!(clock.runs_backwards());
+        assert!(clock.advance(0.016).dt < 0.0);
+
+        // Switching back to an integrator that can't retrace its steps goes forwards again
+        clock.integrator = Integrator::Heun;
+        assert!(clock.advance(0.016).dt > 0.0);
+    }
+
+    #[test]
+    fn step_limit_slows_the_clock_instead_of_stretching_steps() {
+        let mut clock = SimulationClock::default();
+        clock.advance(0.016);
+        assert!(!clock.is_limited());
+
+        clock.max_step = 0.0001;
+        clock.set_speed(MAX_SPEED);
+        let substeps = clock.advance(0.1);
+        assert_eq!(substeps.count, MAX_SUBSTEPS_PER_FRAME);
+        assert!(substeps.dt <= clock.max_step);
+        assert!(clock.is_limited());
+        // 0.1 s at full speed asks for 10000 time units, the capped steps cover 5 of them
+        assert!(
+            (clock.achieved_speed - 5.0).abs() < 0.01,
+            "{}",
+            clock.achieved_speed
+        );
+    }
+}
@@ -0,0 +1,1071 @@
+use image::{Rgba, RgbaImage};
+use nalgebra::{Matrix3, Vector2, Vector3, Vector4};
+
+use crate::{
+    bvh::{self, BvhNode},
+    calculations::{RawPlanetData, GRAV},
+    camera::RawCameraData,
+    postprocess::PostSettings,
+    renderer::{PotentialPlane, RenderMode},
+    sky,
+    surface::SurfaceType,
+};
+
+// Must match fragment.wgsl
+const FOV: f32 = 100.0 * (std::f32::consts::PI / 180.0);
+const MAX_STEPS: usize = 200;
+const HIT_DISTANCE: f32 = 0.001;
+const MAX_DISTANCE: f32 = 2000.0;
+const ATMOSPHERE_STEPS: usize = 12;
+const LIGHT_STEPS: usize = 4;
+const EMISSIVE_INTENSITY: f32 = 4.0;
+const MIN_DOT_SIZE: f32 = 1.0;
+const PLANE_EXTENT: f32 = 4000.0;
+
+// A pure Rust port of fragment.wgsl, used where there is no GPU (tests, headless thumbnails)
+// Each function mirrors the shader function of the same name
+pub struct ReferenceRenderer {
+    background: Option<RgbaImage>,
+    // Exposure and tone mapping are applied like on the GPU, bloom is left out
+    pub post_settings: PostSettings,
+    // Rays per pixel, like SceneInfo::samples
+    pub samples: u32,
+    pub render_mode: RenderMode,
+    pub potential: PotentialPlane,
+}
+
+impl ReferenceRenderer {
+    pub fn new(background: Option<RgbaImage>) -> Self {
+        Self {
+            background,
+            post_settings: PostSettings::default(),
+            samples: 1,
+            render_mode: RenderMode::default(),
+            potential: PotentialPlane::default(),
+        }
+    }
+
+    pub fn render(
+        &self,
+        planets: &[RawPlanetData],
+        camera: &RawCameraData,
+        width: u32,
+        height: u32,
+    ) -> RgbaImage {
+        let mut image = RgbaImage::new(width, height);
+        if width == 0 || height == 0 {
+            return image;
+        }
+
+        let nodes = &bvh::build(planets);
+
+        // Rows are split between threads, every pixel is independent
+        let threads = std::thread::available_parallelism().map_or(1, |count| count.get());
+        let rows_per_thread = (height as usize).div_ceil(threads);
+        let row_bytes = width as usize * 4;
+
+        std::thread::scope(|scope| {
+            for (chunk_index, chunk) in image.chunks_mut(rows_per_thread * row_bytes).enumerate() {
+                scope.spawn(move || {
+                    for (row_offset, row) in chunk.chunks_mut(row_bytes).enumerate() {
+                        let y = (chunk_index * rows_per_thread + row_offset) as u32;
+                        for (x, pixel) in row.chunks_mut(4).enumerate() {
+                            // Same tex_coords the fullscreen quad interpolates, -0.5..0.5 with y up
+                            let tex_coords = Vector2::new(
+                                (x as f32 + 0.5) / width as f32 - 0.5,
+                                0.5 - (y as f32 + 0.5) / height as f32,
+                            );
+                            let color =
+                                self.main(planets, nodes, camera, tex_coords, width, height);
+                            let mapped = self
+                                .post_settings
+                                .tone_mapping
+                                .apply(color.xyz() * self.post_settings.exposure);
+                            pixel.copy_from_slice(&to_srgb8(mapped.push(color.w)).0);
+                        }
+                    }
+                });
+            }
+        });
+
+        image
+    }
+
+    fn main(
+        &self,
+        planets: &[RawPlanetData],
+        nodes: &[BvhNode],
+        camera: &RawCameraData,
+        tex_coords: Vector2<f32>,
+        width: u32,
+        height: u32,
+    ) -> Vector4<f32> {
+        let uv = tex_coords.component_mul(&Vector2::new(width as f32 / height as f32, 1.0));
+
+        let matrix = Matrix3::from_columns(&[
+            Vector3::from_row_slice(&camera.matrix[0][..3]),
+            Vector3::from_row_slice(&camera.matrix[1][..3]),
+            Vector3::from_row_slice(&camera.matrix[2][..3]),
+        ]);
+
+        // Initialization
+        let ray_origin = Vector3::from_row_slice(&camera.pos[..3]);
+        let focal_length = 1.0 / (FOV / 2.0).tan();
+        // uv spans 1 from the bottom of the image to the top
+        let pixel_size = 1.0 / height as f32;
+
+        let samples = self.samples.max(1);
+        let mut color = Vector3::zeros();
+        for i in 0..samples {
+            let sample_uv = uv + sample_offset(i) * pixel_size;
+            let ray_direction = (matrix * sample_uv.push(focal_length)).normalize();
+            color += self.trace(
+                planets,
+                nodes,
+                ray_origin,
+                ray_direction,
+                pixel_size / focal_length,
+            );
+        }
+
+        (color / samples as f32).push(1.0)
+    }
+
+    fn trace(
+        &self,
+        planets: &[RawPlanetData],
+        nodes: &[BvhNode],
+        ray_origin: Vector3<f32>,
+        ray_direction: Vector3<f32>,
+        pixel_angle: f32,
+    ) -> Vector3<f32> {
+        let mut total_dist = 0.0;
+        let mut out_normal = Vector3::zeros();
+
+        let mut surface = SurfaceColor::default();
+        let mut hit_index = None;
+        if self.render_mode == RenderMode::Analytic {
+            let hit = intersect_bodies(planets, nodes, ray_origin, ray_direction);
+            if let Some(hit) = hit {
+                total_dist = hit.distance;
+                let position = ray_origin + ray_direction * total_dist;
+                out_normal = body_normal(&planets[hit.index], position);
+                surface = surface_color(&planets[hit.index], position);
+                hit_index = Some(hit.index);
+            }
+        } else {
+            // Ray marching
+            for i in 0..MAX_STEPS {
+                let position = ray_origin + ray_direction * total_dist;
+
+                let hit = map(planets, nodes, position);
+
+                total_dist += hit.distance;
+
+                if hit.distance < HIT_DISTANCE {
+                    out_normal = get_normal(&planets[hit.index], position);
+                    surface = surface_color(&planets[hit.index], position);
+                    hit_index = Some(hit.index);
+                    break;
+                }
+                if hit.distance > MAX_DISTANCE || i + 1 == MAX_STEPS {
+                    break;
+                }
+            }
+        }
+
+        let fallback = !planets.iter().any(|planet| planet.luminosity > 0.0);
+        let (color, max_distance) = match hit_index {
+            None => {
+                // The shader dims the sky as much as the old half alpha sky blended over black
+                let sky = self.sample_background(sky::direction_to_uv(ray_direction));
+                (sky * 0.25, 1e9)
+            }
+            Some(index) => {
+                let p = ray_origin + ray_direction * total_dist;
+                let color = shade(
+                    planets,
+                    index,
+                    p,
+                    out_normal,
+                    ray_direction,
+                    surface.albedo,
+                    fallback,
+                );
+                (color + surface.emission, total_dist)
+            }
+        };
+
+        let color = apply_dots(
+            planets,
+            ray_origin,
+            ray_direction,
+            max_distance,
+            color,
+            pixel_angle,
+            fallback,
+        );
+        let color = apply_potential_plane(
+            planets,
+            &self.potential,
+            ray_origin,
+            ray_direction,
+            max_distance,
+            color,
+            pixel_angle,
+        );
+        let color = apply_atmospheres(
+            planets,
+            ray_origin,
+            ray_direction,
+            max_distance,
+            color,
+            fallback,
+        );
+        apply_rings(
+            planets,
+            ray_origin,
+            ray_direction,
+            max_distance,
+            color,
+            fallback,
+        )
+    }
+
+    // Bilinear, wrapping horizontally and clamped vertically like the GPU sampler, returns linear color
+    fn sample_background(&self, uv: Vector2<f32>) -> Vector3<f32> {
+        let Some(background) = &self.background else {
+            return Vector3::zeros();
+        };
+
+        let (width, height) = background.dimensions();
+        let x = (uv.x * width as f32 - 0.5).rem_euclid(width as f32);
+        let y = (uv.y * height as f32 - 0.5).clamp(0.0, (height - 1) as f32);
+        let (x0, y0) = ((x.floor() as u32).min(width - 1), y.floor() as u32);
+        let (x1, y1) = ((x0 + 1) % width, (y0 + 1).min(height - 1));
+        let (fx, fy) = (x.fract(), y.fract());
+
+        let texel = |x, y| {
+            let Rgba([r, g, b, _]) = *background.get_pixel(x, y);
+            Vector3::new(r, g, b).map(|channel| srgb_to_linear(channel as f32 / 255.0))
+        };
+
+        let top = texel(x0, y0).lerp(&texel(x1, y0), fx);
+        let bottom = texel(x0, y1).lerp(&texel(x1, y1), fx);
+        top.lerp(&bottom, fy)
+    }
+}
+
+struct Hit {
+    distance: f32,
+    index: usize,
+}
+
+fn map(planets: &[RawPlanetData], nodes: &[BvhNode], p: Vector3<f32>) -> Hit {
+    let mut hit = Hit {
+        distance: 1e9,
+        index: 0,
+    };
+    if nodes.is_empty() {
+        return hit;
+    }
+
+    let mut stack = Vec::with_capacity(bvh::MAX_DEPTH);
+    stack.push(0);
+    while let Some(index) = stack.pop() {
+        let node = &nodes[index];
+        if node.distance(p) >= hit.distance {
+            continue;
+        }
+
+        if node.is_leaf() {
+            let distance = planet_distance(&planets[node.body as usize], p);
+            if distance < hit.distance {
+                hit = Hit {
+                    distance,
+                    index: node.body as usize,
+                };
+            }
+            continue;
+        }
+
+        let (left, right) = (index + 1, node.right as usize);
+        if nodes[left].distance(p) <= nodes[right].distance(p) {
+            stack.extend([right, left]);
+        } else {
+            stack.extend([left, right]);
+        }
+    }
+    hit
+}
+
+fn planet_distance(planet: &RawPlanetData, p: Vector3<f32>) -> f32 {
+    let center = Vector3::from(planet.pos);
+    let sphere = sd_sphere(p - center, planet.radius);
+
+    let amplitude = planet.radius * planet.displacement;
+    if amplitude <= 0.0 {
+        return sphere;
+    }
+    if sphere > amplitude * 2.0 {
+        return sphere - amplitude;
+    }
+
+    let direction = to_body_frame(planet, (p - center).normalize());
+    let height = fbm(direction * planet.feature_scale, planet.seed) * 2.0 - 1.0;
+    (sphere - amplitude * height) * 0.75
+}
+
+fn to_body_frame(planet: &RawPlanetData, v: Vector3<f32>) -> Vector3<f32> {
+    let [x, y, z, w] = planet.orientation;
+    let axis = Vector3::new(-x, -y, -z);
+    let t = 2.0 * axis.cross(&v);
+    v + w * t + axis.cross(&t)
+}
+
+fn sd_sphere(position: Vector3<f32>, s: f32) -> f32 {
+    position.magnitude() - s
+}
+
+fn get_normal(planet: &RawPlanetData, position: Vector3<f32>) -> Vector3<f32> {
+    let d = 0.01;
+    let gradient = |offset: Vector3<f32>| {
+        planet_distance(planet, position + offset) - planet_distance(planet, position - offset)
+    };
+    Vector3::new(
+        gradient(Vector3::new(d, 0.0, 0.0)),
+        gradient(Vector3::new(0.0, d, 0.0)),
+        gradient(Vector3::new(0.0, 0.0, d)),
+    )
+    .normalize()
+}
+
+fn pcg3d(input: [u32; 3]) -> [u32; 3] {
+    let mut v = input.map(|x| x.wrapping_mul(1664525).wrapping_add(1013904223));
+    v[0] = v[0].wrapping_add(v[1].wrapping_mul(v[2]));
+    v[1] = v[1].wrapping_add(v[2].wrapping_mul(v[0]));
+    v[2] = v[2].wrapping_add(v[0].wrapping_mul(v[1]));
+    v = v.map(|x| x ^ (x >> 16));
+    v[0] = v[0].wrapping_add(v[1].wrapping_mul(v[2]));
+    v[1] = v[1].wrapping_add(v[2].wrapping_mul(v[0]));
+    v[2] = v[2].wrapping_add(v[0].wrapping_mul(v[1]));
+    v
+}
+
+fn noise(p: Vector3<f32>, seed: u32) -> f32 {
+    let cell = p.map(f32::floor);
+    let f = p - cell;
+    let t = f.map(|x| x * x * (3.0 - 2.0 * x));
+    let base = [
+        (cell.x as i32 as u32).wrapping_add(seed),
+        cell.y as i32 as u32,
+        cell.z as i32 as u32,
+    ];
+
+    let corner = |i: u32| {
+        let hash = pcg3d([
+            base[0].wrapping_add(i & 1),
+            base[1].wrapping_add((i >> 1) & 1),
+            base[2].wrapping_add((i >> 2) & 1),
+        ]);
+        hash[0] as f32 / 4294967295.0
+    };
+    let mix = |a: f32, b: f32, t: f32| a + (b - a) * t;
+
+    let x0 = mix(corner(0), corner(1), t.x);
+    let x1 = mix(corner(2), corner(3), t.x);
+    let x2 = mix(corner(4), corner(5), t.x);
+    let x3 = mix(corner(6), corner(7), t.x);
+    mix(mix(x0, x1, t.y), mix(x2, x3, t.y), t.z)
+}
+
+fn fbm(p: Vector3<f32>, seed: u32) -> f32 {
+    let mut value = 0.0;
+    let mut amplitude = 0.5;
+    let mut frequency = 1.0;
+    for octave in 0..5 {
+        value += amplitude * noise(p * frequency, seed.wrapping_add(octave));
+        amplitude *= 0.5;
+        frequency *= 2.0;
+    }
+    value / 0.96875
+}
+
+fn ridge(p: Vector3<f32>, seed: u32) -> f32 {
+    1.0 - (fbm(p, seed) * 2.0 - 1.0).abs()
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+#[derive(Default)]
+struct SurfaceColor {
+    albedo: Vector3<f32>,
+    emission: Vector3<f32>,
+}
+
+fn surface_color(planet: &RawPlanetData, position: Vector3<f32>) -> SurfaceColor {
+    let color = Vector3::from(planet.color);
+    let local = to_body_frame(planet, (position - Vector3::from(planet.pos)).normalize());
+    let p = local * planet.feature_scale;
+    let seed = planet.seed;
+
+    let surface_type = SurfaceType::ALL
+        .into_iter()
+        .find(|kind| *kind as u32 == planet.surface_type)
+        .unwrap_or_default();
+    match surface_type {
+        SurfaceType::Plain => SurfaceColor {
+            albedo: color,
+            emission: Vector3::zeros(),
+        },
+        SurfaceType::Rocky => {
+            let height = fbm(p, seed);
+            let detail = fbm(p * 4.0, seed.wrapping_add(7));
+            let ground = color * (0.45 + 0.8 * height) * (0.85 + 0.3 * detail);
+            SurfaceColor {
+                albedo: (color * 0.35).lerp(&ground, smoothstep(0.35, 0.45, height)),
+                emission: Vector3::zeros(),
+            }
+        }
+        SurfaceType::GasGiant => {
+            let turbulence = fbm(p * 2.0, seed);
+            let band = ((local.y * planet.feature_scale * 6.0 + turbulence * 1.5)
+                * std::f32::consts::PI)
+                .sin();
+            let light = color.lerp(&Vector3::repeat(1.0), 0.35);
+            SurfaceColor {
+                albedo: (color * 0.55).lerp(&light, band * 0.5 + 0.5),
+                emission: Vector3::zeros(),
+            }
+        }
+        SurfaceType::Icy => {
+            let ice = color.lerp(&Vector3::new(0.92, 0.96, 1.0), 0.65)
+                * (0.9 + 0.1 * fbm(p * 3.0, seed.wrapping_add(3)));
+            let cracks = smoothstep(0.9, 0.98, ridge(p * 2.0, seed));
+            SurfaceColor {
+                albedo: ice.lerp(&(color * 0.4), cracks * 0.7),
+                emission: Vector3::zeros(),
+            }
+        }
+        SurfaceType::Lava => {
+            let crust = color * (0.1 + 0.1 * fbm(p * 4.0, seed.wrapping_add(5)));
+            let glow = smoothstep(0.94, 0.99, ridge(p * 1.5, seed));
+            SurfaceColor {
+                albedo: crust * (1.0 - glow),
+                emission: Vector3::new(2.0, 0.7, 0.1) * glow,
+            }
+        }
+    }
+}
+
+fn ray_sphere(
+    origin: Vector3<f32>,
+    direction: Vector3<f32>,
+    center: Vector3<f32>,
+    radius: f32,
+) -> Vector2<f32> {
+    let oc = origin - center;
+    let b = oc.dot(&direction);
+    let c = oc.dot(&oc) - radius * radius;
+    let h = b * b - c;
+    if h < 0.0 {
+        return Vector2::new(1.0, -1.0);
+    }
+    let s = h.sqrt();
+    Vector2::new(-b - s, -b + s)
+}
+
+struct Light {
+    direction: Vector3<f32>,
+    distance: f32,
+    color: Vector3<f32>,
+    index: Option<usize>,
+}
+
+fn light_count(planets: &[RawPlanetData], fallback: bool) -> usize {
+    if fallback {
+        1
+    } else {
+        planets.len()
+    }
+}
+
+fn get_light(planets: &[RawPlanetData], i: usize, p: Vector3<f32>, fallback: bool) -> Light {
+    if fallback {
+        return Light {
+            direction: Vector3::new(1.0, 132.0 / 255.0, 0.0).normalize(),
+            distance: 1e9,
+            color: Vector3::repeat(1.0),
+            index: None,
+        };
+    }
+    let planet = &planets[i];
+    let offset = Vector3::from(planet.pos) - p;
+    Light {
+        direction: offset.normalize(),
+        distance: offset.magnitude() - planet.radius,
+        color: Vector3::repeat(1.0).lerp(&Vector3::from(planet.color), 0.3)
+            * planet.luminosity.max(0.0),
+        index: Some(i),
+    }
+}
+
+fn has_ring(planet: &RawPlanetData) -> bool {
+    planet.ring_outer_radius > planet.ring_inner_radius && planet.ring_opacity > 0.0
+}
+
+fn ring_opacity(planet: &RawPlanetData, r: f32) -> f32 {
+    if r < planet.ring_inner_radius || r > planet.ring_outer_radius {
+        return 0.0;
+    }
+    let u = (r - planet.ring_inner_radius) / (planet.ring_outer_radius - planet.ring_inner_radius);
+    let coarse = noise(
+        Vector3::new(u * 12.0, 0.0, 0.0),
+        planet.seed.wrapping_add(11),
+    );
+    let fine = noise(
+        Vector3::new(u * 60.0, 0.0, 0.0),
+        planet.seed.wrapping_add(13),
+    );
+    let bands = smoothstep(0.25, 0.75, coarse) * (0.5 + 0.5 * fine);
+    let edges = smoothstep(0.0, 0.05, u) * (1.0 - smoothstep(0.95, 1.0, u));
+    planet.ring_opacity * bands * edges
+}
+
+fn ring_distance(origin: Vector3<f32>, direction: Vector3<f32>, planet: &RawPlanetData) -> f32 {
+    let normal = Vector3::from(planet.ring_normal);
+    let facing = direction.dot(&normal);
+    if facing.abs() < 1e-6 {
+        return -1.0;
+    }
+    (Vector3::from(planet.pos) - origin).dot(&normal) / facing
+}
+
+fn light_visibility(
+    planets: &[RawPlanetData],
+    p: Vector3<f32>,
+    light: &Light,
+    ignored: Option<usize>,
+) -> f32 {
+    let mut visibility = 1.0;
+    for (i, planet) in planets.iter().enumerate() {
+        if Some(i) == light.index {
+            continue;
+        }
+        if Some(i) != ignored {
+            let t = ray_sphere(p, light.direction, Vector3::from(planet.pos), planet.radius);
+            if t.x <= t.y && t.y > 0.0 && t.x < light.distance {
+                return 0.0;
+            }
+        }
+        if has_ring(planet) {
+            let t = ring_distance(p, light.direction, planet);
+            if t > 0.001 * planet.radius && t < light.distance {
+                let r = (p + light.direction * t - Vector3::from(planet.pos)).magnitude();
+                visibility *= 1.0 - ring_opacity(planet, r);
+            }
+        }
+    }
+    visibility
+}
+
+fn apply_rings(
+    planets: &[RawPlanetData],
+    origin: Vector3<f32>,
+    direction: Vector3<f32>,
+    max_distance: f32,
+    background: Vector3<f32>,
+    fallback: bool,
+) -> Vector3<f32> {
+    let mut color = background;
+    for (i, planet) in planets.iter().enumerate() {
+        if !has_ring(planet) {
+            continue;
+        }
+
+        let t = ring_distance(origin, direction, planet);
+        if t <= 0.0 || t >= max_distance {
+            continue;
+        }
+        let p = origin + direction * t;
+        let r = (p - Vector3::from(planet.pos)).magnitude();
+        let opacity = ring_opacity(planet, r);
+        if opacity <= 0.0 {
+            continue;
+        }
+
+        let mut lighting = Vector3::zeros();
+        for j in 0..light_count(planets, fallback) {
+            let light = get_light(planets, j, p, fallback);
+            if light.index == Some(i) || light.color == Vector3::zeros() {
+                continue;
+            }
+            let visibility = light_visibility(planets, p, &light, None);
+            let facing = Vector3::from(planet.ring_normal)
+                .dot(&light.direction)
+                .abs();
+            lighting += light.color * visibility * (0.25 + 0.75 * facing);
+        }
+
+        let u =
+            (r - planet.ring_inner_radius) / (planet.ring_outer_radius - planet.ring_inner_radius);
+        let shade = 0.8
+            + 0.4
+                * noise(
+                    Vector3::new(u * 60.0, 0.0, 0.0),
+                    planet.seed.wrapping_add(13),
+                );
+        let ring_color = (Vector3::from(planet.ring_color) * shade).component_mul(&lighting);
+        color = color.lerp(&ring_color, opacity);
+    }
+    color
+}
+
+fn has_atmosphere(planet: &RawPlanetData) -> bool {
+    planet.atmosphere_height > 0.0 && planet.atmosphere_density > 0.0
+}
+
+fn atmosphere_density(planet: &RawPlanetData, p: Vector3<f32>) -> f32 {
+    let thickness = planet.radius * planet.atmosphere_height;
+    let altitude = ((p - Vector3::from(planet.pos)).magnitude() - planet.radius).max(0.0);
+    planet.atmosphere_density * 4.0 / thickness * (-4.0 * altitude / thickness).exp()
+}
+
+fn optical_depth(planet: &RawPlanetData, p: Vector3<f32>, direction: Vector3<f32>) -> f32 {
+    let shell = ray_sphere(
+        p,
+        direction,
+        Vector3::from(planet.pos),
+        planet.radius * (1.0 + planet.atmosphere_height),
+    );
+    let step_size = shell.y.max(0.0) / LIGHT_STEPS as f32;
+    (0..LIGHT_STEPS)
+        .map(|i| {
+            atmosphere_density(planet, p + direction * step_size * (i as f32 + 0.5)) * step_size
+        })
+        .sum()
+}
+
+fn extinction(planet: &RawPlanetData, depth: f32) -> Vector3<f32> {
+    Vector3::from(planet.atmosphere_color).map(|channel| (-channel * depth).exp())
+}
+
+fn apply_atmospheres(
+    planets: &[RawPlanetData],
+    origin: Vector3<f32>,
+    direction: Vector3<f32>,
+    max_distance: f32,
+    background: Vector3<f32>,
+    fallback: bool,
+) -> Vector3<f32> {
+    let mut color = background;
+    for planet in planets.iter().filter(|planet| has_atmosphere(planet)) {
+        let shell = ray_sphere(
+            origin,
+            direction,
+            Vector3::from(planet.pos),
+            planet.radius * (1.0 + planet.atmosphere_height),
+        );
+        let start = shell.x.max(0.0);
+        let end = shell.y.min(max_distance);
+        if end <= start {
+            continue;
+        }
+
+        let step_size = (end - start) / ATMOSPHERE_STEPS as f32;
+        let mut view_depth = 0.0;
+        let mut inscattered = Vector3::zeros();
+        for step in 0..ATMOSPHERE_STEPS {
+            let p = origin + direction * (start + step_size * (step as f32 + 0.5));
+            let density = atmosphere_density(planet, p) * step_size;
+            view_depth += density * 0.5;
+
+            for j in 0..light_count(planets, fallback) {
+                let light = get_light(planets, j, p, fallback);
+                if light.color == Vector3::zeros() {
+                    continue;
+                }
+                let visibility = light_visibility(planets, p, &light, None);
+                if visibility <= 0.0 {
+                    continue;
+                }
+                let light_depth = optical_depth(planet, p, light.direction);
+                let transmittance = extinction(planet, view_depth + light_depth) * visibility;
+                let cos_theta = direction.dot(&light.direction);
+                let phase = 0.75 * (1.0 + cos_theta * cos_theta);
+                inscattered += light.color.component_mul(&transmittance) * phase * density;
+            }
+
+            view_depth += density * 0.5;
+        }
+
+        color = color.component_mul(&extinction(planet, view_depth))
+            + inscattered.component_mul(&Vector3::from(planet.atmosphere_color));
+    }
+    color
+}
+
+fn apply_dots(
+    planets: &[RawPlanetData],
+    origin: Vector3<f32>,
+    direction: Vector3<f32>,
+    max_distance: f32,
+    background: Vector3<f32>,
+    pixel_angle: f32,
+    fallback: bool,
+) -> Vector3<f32> {
+    let mut color = background;
+    for (i, planet) in planets.iter().enumerate() {
+        let to_center = Vector3::from(planet.pos) - origin;
+        let t = to_center.dot(&direction);
+        if t <= 0.0 || t >= max_distance {
+            continue;
+        }
+
+        let footprint = t * pixel_angle * MIN_DOT_SIZE;
+        let size = planet.radius / footprint;
+        let offset = (to_center - direction * t).magnitude() / footprint;
+        let coverage = (1.0 - smoothstep(0.5, 1.0, offset)) * (1.0 - smoothstep(0.5, 1.0, size));
+        if coverage <= 0.0 {
+            continue;
+        }
+
+        let p = Vector3::from(planet.pos) - direction * planet.radius;
+        let surface = surface_color(planet, p);
+        let dot_color = shade(
+            planets,
+            i,
+            p,
+            -direction,
+            direction,
+            surface.albedo,
+            fallback,
+        ) + surface.emission;
+        color = color.lerp(&dot_color, coverage);
+    }
+    color
+}
+
+// Potential and its gradient, softened inside each body so the wells stay finite
+fn potential(planets: &[RawPlanetData], p: Vector3<f32>) -> (f32, Vector3<f32>) {
+    let mut value = 0.0;
+    let mut gradient = Vector3::zeros();
+    for planet in planets {
+        let offset = p - Vector3::from(planet.pos);
+        let inverse_distance =
+            1.0 / (offset.magnitude_squared() + planet.radius * planet.radius).sqrt();
+        value -= GRAV * planet.mass * inverse_distance;
+        gradient += offset * (GRAV * planet.mass * inverse_distance.powi(3));
+    }
+    (value, gradient)
+}
+
+fn potential_color(well: f32) -> Vector3<f32> {
+    let shallow = Vector3::new(0.03, 0.08, 0.3);
+    let middle = Vector3::new(0.45, 0.1, 0.55);
+    let deep = Vector3::new(1.0, 0.55, 0.15);
+    if well < 0.5 {
+        shallow.lerp(&middle, well * 2.0)
+    } else {
+        middle.lerp(&deep, well * 2.0 - 1.0)
+    }
+}
+
+fn apply_potential_plane(
+    planets: &[RawPlanetData],
+    plane: &PotentialPlane,
+    origin: Vector3<f32>,
+    direction: Vector3<f32>,
+    max_distance: f32,
+    background: Vector3<f32>,
+    pixel_angle: f32,
+) -> Vector3<f32> {
+    if !plane.visible || direction.y.abs() < 1e-6 {
+        return background;
+    }
+    let t = (plane.height - origin.y) / direction.y;
+    if t <= 0.0 || t >= max_distance {
+        return background;
+    }
+
+    let p = origin + direction * t;
+    let (value, gradient) = potential(planets, p);
+    let depth = -value;
+    if depth <= 0.0 {
+        return background;
+    }
+
+    let level = depth.log2() * plane.contour_density;
+    let footprint = t * pixel_angle / direction.y.abs();
+    let level_per_pixel = (gradient.magnitude() / (depth * std::f32::consts::LN_2)
+        * plane.contour_density
+        * footprint)
+        .max(1e-6);
+    // WGSL's fract floors, so negative levels wrap the same way
+    let line_distance = ((level + 0.5).rem_euclid(1.0) - 0.5).abs() / level_per_pixel;
+    let line = (1.0 - smoothstep(0.5, 1.5, line_distance))
+        * (1.0 - smoothstep(0.25, 0.5, level_per_pixel));
+
+    let well = ((depth.log2() - 3.0) / 8.0).clamp(0.0, 1.0);
+    let fade = 1.0
+        - smoothstep(
+            PLANE_EXTENT * 0.5,
+            PLANE_EXTENT,
+            Vector2::new(p.x, p.z).magnitude(),
+        );
+    let color = potential_color(well) + Vector3::new(0.8, 0.85, 1.0) * line;
+    background.lerp(&color, (0.35 + 0.4 * well) * fade)
+}
+
+fn ray_box(origin: Vector3<f32>, inverse_direction: Vector3<f32>, node: &BvhNode) -> (f32, f32) {
+    let t0 = (Vector3::from(node.min) - origin).component_mul(&inverse_direction);
+    let t1 = (Vector3::from(node.max) - origin).component_mul(&inverse_direction);
+    let near = t0.inf(&t1);
+    let far = t0.sup(&t1);
+    (near.max().max(0.0), far.min())
+}
+
+const TERRAIN_STEPS: usize = 64;
+
+fn intersect_body(
+    planet: &RawPlanetData,
+    origin: Vector3<f32>,
+    direction: Vector3<f32>,
+    max_distance: f32,
+) -> Option<f32> {
+    let amplitude = planet.radius * planet.displacement.max(0.0);
+    let bounds = ray_sphere(
+        origin,
+        direction,
+        Vector3::from(planet.pos),
+        planet.radius + amplitude,
+    );
+    let (near, far) = (bounds.x, bounds.y);
+    if near > far || far < 0.0 || near >= max_distance {
+        return None;
+    }
+    let entry = near.max(0.0);
+    if amplitude <= 0.0 {
+        return Some(entry);
+    }
+
+    let mut t = entry;
+    let exit = far.min(max_distance);
+    for _ in 0..TERRAIN_STEPS {
+        if t >= exit {
+            break;
+        }
+        let distance = planet_distance(planet, origin + direction * t);
+        if distance < HIT_DISTANCE {
+            return Some(t);
+        }
+        t += distance;
+    }
+    None
+}
+
+fn intersect_bodies(
+    planets: &[RawPlanetData],
+    nodes: &[BvhNode],
+    origin: Vector3<f32>,
+    direction: Vector3<f32>,
+) -> Option<Hit> {
+    let mut hit: Option<Hit> = None;
+    if nodes.is_empty() {
+        return hit;
+    }
+    let inverse_direction = direction.map(|x| 1.0 / if x.abs() < 1e-8 { 1e-8 } else { x });
+
+    let mut stack = Vec::with_capacity(bvh::MAX_DEPTH);
+    stack.push(0);
+    while let Some(index) = stack.pop() {
+        let node = &nodes[index];
+        let closest = hit.as_ref().map_or(1e9, |hit| hit.distance);
+        let (near, far) = ray_box(origin, inverse_direction, node);
+        if near > far || near >= closest {
+            continue;
+        }
+
+        if node.is_leaf() {
+            let planet = &planets[node.body as usize];
+            if let Some(distance) = intersect_body(planet, origin, direction, closest) {
+                hit = Some(Hit {
+                    distance,
+                    index: node.body as usize,
+                });
+            }
+            continue;
+        }
+
+        let (left, right) = (index + 1, node.right as usize);
+        let left_near = ray_box(origin, inverse_direction, &nodes[left]).0;
+        let right_near = ray_box(origin, inverse_direction, &nodes[right]).0;
+        if left_near <= right_near {
+            stack.extend([right, left]);
+        } else {
+            stack.extend([left, right]);
+        }
+    }
+    hit
+}
+
+fn body_normal(planet: &RawPlanetData, position: Vector3<f32>) -> Vector3<f32> {
+    if planet.displacement <= 0.0 {
+        return (position - Vector3::from(planet.pos)).normalize();
+    }
+    get_normal(planet, position)
+}
+
+fn sample_offset(i: u32) -> Vector2<f32> {
+    (Vector2::repeat(0.5) + Vector2::new(0.7548777, 0.5698403) * i as f32).map(f32::fract)
+        - Vector2::repeat(0.5)
+}
+
+fn shade(
+    planets: &[RawPlanetData],
+    index: usize,
+    p: Vector3<f32>,
+    normal: Vector3<f32>,
+    view_direction: Vector3<f32>,
+    albedo: Vector3<f32>,
+    fallback: bool,
+) -> Vector3<f32> {
+    let planet = &planets[index];
+
+    if planet.luminosity > 0.0 {
+        return albedo
+            * planet.luminosity
+            * EMISSIVE_INTENSITY
+            * (0.6 + 0.4 * normal.dot(&-view_direction).max(0.0));
+    }
+
+    let mut color = Vector3::zeros();
+    for j in 0..light_count(planets, fallback) {
+        let light = get_light(planets, j, p, fallback);
+        let diffuse_strength = light.direction.dot(&normal);
+        if light.color == Vector3::zeros() || diffuse_strength <= 0.0 {
+            continue;
+        }
+        let visibility = light_visibility(planets, p, &light, Some(index));
+        if visibility <= 0.0 {
+            continue;
+        }
+
+        let mut light_color = light.color * visibility;
+        if has_atmosphere(planet) {
+            light_color.component_mul_assign(&extinction(
+                planet,
+                optical_depth(planet, p, light.direction),
+            ));
+        }
+
+        let diffuse = albedo * diffuse_strength;
+        let specular_strength = (-view_direction)
+            .dot(&reflect(-light.direction, normal))
+            .max(0.0)
+            .powf(64.0);
+        color += light_color
+            .component_mul(&(diffuse * 0.75 + Vector3::repeat(specular_strength * 0.25)));
+    }
+    color
+}
+
+// Same as the WGSL builtin
+fn reflect(incident: Vector3<f32>, normal: Vector3<f32>) -> Vector3<f32> {
+    incident - 2.0 * normal.dot(&incident) * normal
+}
+
+fn srgb_to_linear(channel: f32) -> f32 {
+    if channel <= 0.04045 {
+        channel / 12.92
+    } else {
+        ((channel + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(channel: f32) -> f32 {
+    if channel <= 0.0031308 {
+        channel * 12.92
+    } else {
+        1.055 * channel.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+// The window surface is sRGB, so the shader's linear output gets encoded on write
+fn to_srgb8(color: Vector4<f32>) -> Rgba<u8> {
+    let encode = |channel: f32| (linear_to_srgb(channel.clamp(0.0, 1.0)) * 255.0).round() as u8;
+    Rgba([
+        encode(color.x),
+        encode(color.y),
+        encode(color.z),
+        (color.w.clamp(0.0, 1.0) * 255.0).round() as u8,
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{calculations::Planet, camera::Camera};
+
+    const WIDTH: u32 = 120;
+    const HEIGHT: u32 = 80;
+    const SKY: u8 = 64;
+
+    // A star and a plain planet side by side, seen from the side so the planet's left half faces the star.
+    // The sky is a flat gray so the planet's unlit half shows up against it
+    fn render_scene(render_mode: RenderMode) -> RgbaImage {
+        let mut star = Planet::new(10000.0, [0.0; 3], 35.0, [1.0, 0.9, 0.7]);
+        star.luminosity = 1.0;
+        let planet = Planet::new(10.0, [300.0, 0.0, 0.0], 40.0, [0.6, 0.6, 0.6]);
+
+        let mut camera = Camera::starting_view();
+        camera.look_at(
+            Vector3::new(150.0, 0.0, -400.0),
+            Vector3::new(150.0, 0.0, 0.0),
+        );
+
+        let sky = RgbaImage::from_pixel(4, 2, Rgba([SKY, SKY, SKY, 255]));
+        let mut renderer = ReferenceRenderer::new(Some(sky));
+        renderer.render_mode = render_mode;
+        renderer.render(
+            &[star.to_raw_data(), planet.to_raw_data()],
+            &camera.to_raw_data(),
+            WIDTH,
+            HEIGHT,
+        )
+    }
+
+    fn brightness(image: &RgbaImage, x: u32, y: u32) -> f32 {
+        let Rgba([r, g, b, _]) = *image.get_pixel(x, y);
+        (r as f32 + g as f32 + b as f32) / 3.0
+    }
+
+    // Pixels picked for the scene above: the star's center, the planet's sides along the middle row
+    // and sky between and around them. Both render modes have to agree on every one
+    #[test]
+    fn renders_hits_sky_and_lighting() {
+        for render_mode in RenderMode::ALL {
+            let image = render_scene(render_mode);
+            let sky = brightness(&image, 60, 40);
+            let message = format!("{render_mode:?}");
+
+            for (x, y) in [(5, 5), (60, 40), (115, 75), (34, 10)] {
+                assert!(
+                    (brightness(&image, x, y) - sky).abs() < 1.0,
+                    "sky at {x},{y} {message}"
+                );
+            }
+            assert!(sky > 5.0, "sky {sky} {message}");
+            assert!(brightness(&image, 34, 40) > 200.0, "star {message}");
+
+            // The side facing the star is lit, the far side is in shadow and hides the sky behind it
+            let lit = brightness(&image, 80, 40);
+            let shadowed = brightness(&image, 90, 40);
+            assert!(lit > 120.0, "lit side {lit} {message}");
+            assert!(shadowed < sky - 5.0, "shadowed side {shadowed} {message}");
+        }
+    }
+}
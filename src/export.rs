@@ -0,0 +1,148 @@
+use std::path::{Path, PathBuf};
+
+use image::RgbaImage;
+use nalgebra::Vector3;
+
+use crate::clock::Substeps;
+
+// Where the camera is and what it looks at, at a point in the exported video
+#[derive(Debug, Clone, Copy)]
+pub struct CameraKeyframe {
+    pub time: f32, // Seconds into the video
+    pub pos: Vector3<f32>,
+    pub target: Vector3<f32>,
+}
+
+// Camera keyframes sorted by time, interpolated between neighbours
+#[derive(Debug, Clone, Default)]
+pub struct CameraPath {
+    pub keyframes: Vec<CameraKeyframe>,
+}
+
+impl CameraPath {
+    // One keyframe per line: time x y z target_x target_y target_z, # starts a comment
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|err| format!("Failed to read {}: {err}", path.display()))?;
+
+        let mut keyframes = contents
+            .lines()
+            .enumerate()
+            .map(|(line_number, line)| (line_number, line.split('#').next().unwrap().trim()))
+            .filter(|(_, line)| !line.is_empty())
+            .map(|(line_number, line)| {
+                let values = line
+                    .split_whitespace()
+                    .map(str::parse::<f32>)
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|err| {
+                        format!("Invalid keyframe on line {}: {err}", line_number + 1)
+                    })?;
+                match values[..] {
+                    [time, x, y, z, target_x, target_y, target_z] => Ok(CameraKeyframe {
+                        time,
+                        pos: Vector3::new(x, y, z),
+                        target: Vector3::new(target_x, target_y, target_z),
+                    }),
+                    _ => Err(format!(
+                        "Keyframe on line {} needs 7 values: time x y z target_x target_y target_z",
+                        line_number + 1
+                    )),
+                }
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+        Ok(Self { keyframes })
+    }
+
+    // Camera position and look-at target at a time, None if there are no keyframes
+    pub fn sample(&self, time: f32) -> Option<(Vector3<f32>, Vector3<f32>)> {
+        let next_index = self
+            .keyframes
+            .partition_point(|keyframe| keyframe.time <= time);
+
+        // Before the first keyframe the camera holds its starting position
+        if next_index == 0 {
+            let first = self.keyframes.first()?;
+            return Some((first.pos, first.target));
+        }
+
+        let previous = &self.keyframes[next_index - 1];
+        match self.keyframes.get(next_index) {
+            Some(next) => {
+                // Smoothstep so the camera eases in and out of each keyframe
+                let t = (time - previous.time) / (next.time - previous.time);
+                let t = t * t * (3.0 - 2.0 * t);
+                Some((
+                    previous.pos.lerp(&next.pos, t),
+                    previous.target.lerp(&next.target, t),
+                ))
+            }
+            None => Some((previous.pos, previous.target)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ExportSettings {
+    pub directory: PathBuf,
+    pub frame_count: u32,
+    pub fps: f32,
+    pub size: [u32; 2],
+    pub supersample: u32,
+    // Simulated time that passes between two frames, independent of how long a frame takes to render
+    pub frame_dt: f32,
+    // Longest physics step, like SimulationClock::max_step
+    pub max_step: f32,
+    pub camera_path: CameraPath,
+}
+
+// Renders a fixed number of frames to numbered PNGs
+#[derive(Debug)]
+pub struct FrameExporter {
+    pub settings: ExportSettings,
+    pub frame: u32,
+}
+
+impl FrameExporter {
+    pub fn new(settings: ExportSettings) -> std::io::Result<Self> {
+        std::fs::create_dir_all(&settings.directory)?;
+        Ok(Self { settings, frame: 0 })
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.frame >= self.settings.frame_count
+    }
+
+    // Seconds into the video of the frame about to be rendered
+    pub fn video_time(&self) -> f32 {
+        self.frame as f32 / self.settings.fps
+    }
+
+    pub fn camera(&self) -> Option<(Vector3<f32>, Vector3<f32>)> {
+        self.settings.camera_path.sample(self.video_time())
+    }
+
+    // Physics steps between two frames, split the same way as the live clock
+    pub fn substeps(&self) -> Substeps {
+        let count = (self.settings.frame_dt.abs() / self.settings.max_step)
+            .ceil()
+            .max(1.0) as u32;
+        Substeps {
+            count,
+            dt: self.settings.frame_dt / count as f32,
+        }
+    }
+
+    pub fn save_frame(&mut self, image: &RgbaImage) -> image::ImageResult<PathBuf> {
+        let path = self
+            .settings
+            .directory
+            .join(format!("frame_{:05}.png", self.frame));
+        image.save_with_format(&path, image::ImageFormat::Png)?;
+
+        self.frame += 1;
+        Ok(path)
+    }
+}
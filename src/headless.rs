@@ -0,0 +1,71 @@
+use std::io;
+
+use crate::{
+    calculations::{step_system, Planet},
+    camera::Camera,
+    cli::Args,
+    cpu_renderer::ReferenceRenderer,
+    recorder::TrajectoryRecorder,
+    sky,
+    units::Quantity,
+};
+
+// Run the simulation for the requested duration without opening a window
+pub fn run(args: &Args, planets: &mut [Planet]) -> io::Result<()> {
+    let mut recorder = args
+        .record
+        .as_deref()
+        .map(|path| {
+            TrajectoryRecorder::create(path, args.record_format(), args.record_interval, args.units)
+        })
+        .transpose()?;
+
+    let dt = args.dt();
+    let steps = (args.duration / dt as f64).ceil() as u64;
+    let mut time = 0.0;
+
+    if let Some(recorder) = &mut recorder {
+        recorder.record(time, planets)?;
+    }
+
+    for _ in 0..steps {
+        step_system(planets, dt, args.integrator);
+        time += dt as f64;
+
+        if let Some(recorder) = &mut recorder {
+            recorder.record(time, planets)?;
+        }
+    }
+
+    println!(
+        "Simulated {steps} steps of {} ({})",
+        args.units.format(dt as f64, Quantity::Time),
+        args.units.format(time, Quantity::Time)
+    );
+    if let Some(recorder) = recorder {
+        let samples = recorder.samples;
+        let path = recorder.finish()?;
+        println!("Recorded {samples} samples to {}", path.display());
+    }
+
+    if let Some(path) = &args.thumbnail {
+        let [width, height] = args.thumbnail_size;
+        let raw_planets = planets.iter().map(|p| p.to_raw_data()).collect::<Vec<_>>();
+        let background = sky::load_or_default(args.sky.as_deref()).map_err(io::Error::other)?;
+        let mut reference = ReferenceRenderer::new(Some(background));
+        reference.post_settings = args.post_settings();
+        reference.samples = args.samples;
+        reference.render_mode = args.render_mode;
+        reference.potential = args.potential_plane();
+        let image = reference.render(
+            &raw_planets,
+            &Camera::starting_view().to_raw_data(),
+            width,
+            height,
+        );
+        image.save(path).map_err(io::Error::other)?;
+        println!("Saved thumbnail to {}", path.display());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,555 @@
+use std::path::{Path, PathBuf};
+
+use nalgebra::{Rotation3, Vector3};
+
+use crate::{
+    calculations::Planet,
+    orbit,
+    scenario::Scenario,
+    surface::{Surface, SurfaceType},
+    units::{Quantity, Units, ASTRONOMICAL_UNIT, SI_GRAV},
+};
+
+// Angle between the ICRF equator and the ecliptic of J2000 that Horizons uses, 84381.448 arcseconds, in degrees
+const OBLIQUITY: f64 = 84381.448 / 3600.0;
+// For targets the header and the bundled solar system have no size for, like spacecraft
+const DEFAULT_RADIUS: f64 = 1000.0; // km
+const DEFAULT_COLOR: [f32; 3] = [0.6, 0.6, 0.6];
+
+// Components of a state in the order StateVector keeps them, as Horizons labels them
+const COMPONENTS: [&str; 6] = ["X", "Y", "Z", "VX", "VY", "VZ"];
+
+// The plane a table's x-y axes lie in, both have x towards the J2000 equinox
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReferencePlane {
+    Ecliptic,
+    // ICRF, Earth's mean equator at J2000
+    Equator,
+}
+
+// One record of the $$SOE / $$EOE block
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StateVector {
+    pub julian_date: f64,
+    pub pos: Vector3<f64>,
+    pub vel: Vector3<f64>,
+}
+
+// A JPL Horizons VECTORS table for one target, the states are in the table's units and plane
+#[derive(Debug, Clone)]
+pub struct Ephemeris {
+    pub target: String,
+    pub center: String,
+    // G times the target's mass in km^3/s^2 and its mean radius in km, when the header lists them
+    pub gm: Option<f64>,
+    pub radius: Option<f64>,
+    // One length and time unit of the table in meters and seconds
+    pub length_unit: f64,
+    pub time_unit: f64,
+    pub plane: ReferencePlane,
+    pub states: Vec<StateVector>,
+}
+
+impl Ephemeris {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|err| format!("Failed to read {}: {err}", path.display()))?;
+        Self::parse(&contents).map_err(|err| format!("{}: {err}", path.display()))
+    }
+
+    // The text Horizons writes for a VECTORS request, with or without CSV_FORMAT
+    // States need velocities, so the table type has to be 2 or more
+    pub fn parse(contents: &str) -> Result<Self, String> {
+        let (header, rows) = contents
+            .split_once("$$SOE")
+            .ok_or("No $$SOE line, is this a Horizons VECTORS table?")?;
+        let (rows, _) = rows
+            .split_once("$$EOE")
+            .ok_or("No $$EOE line after $$SOE")?;
+
+        let mut table = Self {
+            target: String::new(),
+            center: String::new(),
+            gm: None,
+            radius: None,
+            length_unit: 1000.0,
+            time_unit: 1.0,
+            plane: ReferencePlane::Ecliptic,
+            states: Vec::new(),
+        };
+        // Column labels of a CSV table, the line before the block lists them
+        let mut columns = None;
+
+        for line in header.lines() {
+            let field = |name: &str| {
+                line.trim_start()
+                    .strip_prefix(name)
+                    .map(|value| value.trim_start().trim_start_matches(':').trim())
+            };
+
+            if let Some(value) = field("Target body name") {
+                table.target = body_name(value);
+            } else if let Some(value) = field("Center body name") {
+                table.center = body_name(value);
+            } else if let Some(value) = field("Output units") {
+                let units = value.split_whitespace().next().unwrap_or_default();
+                let (length, time) = units.split_once('-').unwrap_or((units, ""));
+                table.length_unit = match length {
+                    "KM" => 1000.0,
+                    "AU" => ASTRONOMICAL_UNIT,
+                    _ => return Err(format!("Unsupported output units: {value}")),
+                };
+                table.time_unit = match time {
+                    "S" => 1.0,
+                    "D" => 86400.0,
+                    _ => return Err(format!("Unsupported output units: {value}")),
+                };
+            } else if let Some(value) = field("Reference frame") {
+                let frame = value.to_ascii_lowercase();
+                table.plane = if frame.contains("ecliptic") {
+                    ReferencePlane::Ecliptic
+                } else if frame.contains("icrf") {
+                    ReferencePlane::Equator
+                } else {
+                    return Err(format!("Unsupported reference frame: {value}"));
+                };
+            } else if line.contains(',') {
+                let labels = line.split(',').map(str::trim).collect::<Vec<_>>();
+                if labels.contains(&"X") && labels.contains(&"VX") {
+                    columns = Some(labels);
+                }
+            }
+
+            // Physical data sits in two columns, so look for the value after the label
+            for label in ["GM, km^3/s^2", "GM (km^3/s^2)"] {
+                if let Some(start) = line.find(label) {
+                    table.gm = table.gm.or(value_after(&line[start..]));
+                }
+            }
+            if let Some(start) = line.to_ascii_lowercase().find("mean radius") {
+                table.radius = table.radius.or(value_after(&line[start..]));
+            }
+        }
+
+        if table.target.is_empty() {
+            return Err("No target body name in the header".to_string());
+        }
+
+        let first_line = header.lines().count() + 1;
+        let mut state: Option<PartialState> = None;
+        for (index, line) in rows.lines().enumerate() {
+            let line_number = first_line + index;
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let invalid = |what: &str| format!("Invalid {what} on line {line_number}");
+
+            if line.contains(',') {
+                // CSV tables have a whole record on each line
+                let fields = line.split(',').map(str::trim).collect::<Vec<_>>();
+                let mut record = PartialState::new(fields[0], line_number)?;
+                for (i, component) in COMPONENTS.into_iter().enumerate() {
+                    let column = match &columns {
+                        Some(labels) => labels.iter().position(|label| *label == component),
+                        // The order Horizons writes them in, after the date columns
+                        None => Some(i + 2),
+                    };
+                    if let Some(field) = column.and_then(|column| fields.get(column)) {
+                        record.values[i] = Some(field.parse().map_err(|_| invalid(component))?);
+                    }
+                }
+                table.states.push(record.finish()?);
+            } else if line
+                .split_whitespace()
+                .next()
+                .unwrap()
+                .parse::<f64>()
+                .is_ok()
+            {
+                // A record starts with its Julian date, the components follow on their own lines
+                if let Some(state) = state.take() {
+                    table.states.push(state.finish()?);
+                }
+                state = Some(PartialState::new(line, line_number)?);
+            } else {
+                let state = state
+                    .as_mut()
+                    .ok_or_else(|| format!("Values before the first date on line {line_number}"))?;
+                // Lines read like " X =-2.6E+07 Y = 1.4E+08 Z =-3.8E+01", label then value
+                let parts = line.split('=').collect::<Vec<_>>();
+                for pair in parts.windows(2) {
+                    let label = pair[0].split_whitespace().last().unwrap_or_default();
+                    if let Some(i) = COMPONENTS.iter().position(|c| *c == label) {
+                        let value = pair[1].split_whitespace().next().unwrap_or_default();
+                        state.values[i] = Some(value.parse().map_err(|_| invalid(label))?);
+                    }
+                }
+            }
+        }
+        if let Some(state) = state {
+            table.states.push(state.finish()?);
+        }
+
+        if table.states.is_empty() {
+            return Err("No states between $$SOE and $$EOE".to_string());
+        }
+        Ok(table)
+    }
+
+    // The target's mass in simulation units, from G times the mass in the header
+    pub fn mass(&self) -> Option<f64> {
+        self.gm
+            .map(|gm| Units::Si.to_sim(gm * 1e9 / SI_GRAV, Quantity::Mass))
+    }
+
+    // Position and velocity relative to the center in simulation units and the simulation's frame
+    pub fn world_state(&self, state: &StateVector) -> (Vector3<f64>, Vector3<f64>) {
+        let length = Units::Si.to_sim(self.length_unit, Quantity::Length);
+        let velocity = Units::Si.to_sim(self.length_unit / self.time_unit, Quantity::Velocity);
+        let (pos, vel) = match self.plane {
+            ReferencePlane::Ecliptic => (state.pos, state.vel),
+            ReferencePlane::Equator => {
+                let rotation =
+                    Rotation3::from_axis_angle(&Vector3::x_axis(), -OBLIQUITY.to_radians());
+                (rotation * state.pos, rotation * state.vel)
+            }
+        };
+        (
+            orbit::ecliptic_to_world(pos * length),
+            orbit::ecliptic_to_world(vel * velocity),
+        )
+    }
+}
+
+// A record read so far, its components are filled in as their lines come
+struct PartialState {
+    julian_date: f64,
+    line_number: usize,
+    values: [Option<f64>; 6],
+}
+
+impl PartialState {
+    fn new(line: &str, line_number: usize) -> Result<Self, String> {
+        let date = line.split([' ', ',']).next().unwrap_or_default();
+        Ok(Self {
+            julian_date: date
+                .parse()
+                .map_err(|_| format!("Invalid date on line {line_number}"))?,
+            line_number,
+            values: [None; 6],
+        })
+    }
+
+    fn finish(self) -> Result<StateVector, String> {
+        let mut values = [0.0; 6];
+        for (i, value) in self.values.into_iter().enumerate() {
+            values[i] = value.ok_or_else(|| {
+                format!(
+                    "Record on line {} has no {}, export the table with velocities",
+                    self.line_number, COMPONENTS[i]
+                )
+            })?;
+        }
+        Ok(StateVector {
+            julian_date: self.julian_date,
+            pos: Vector3::new(values[0], values[1], values[2]),
+            vel: Vector3::new(values[3], values[4], values[5]),
+        })
+    }
+}
+
+// "Earth (399)     {source: DE441}" becomes "Earth"
+fn body_name(value: &str) -> String {
+    value.split(['(', '{']).next().unwrap().trim().to_string()
+}
+
+// The number after the first = in text like "GM, km^3/s^2 = 398600.435436  Mass ratio ...",
+// uncertainties like "6371.01+-0.02" are left off
+fn value_after(text: &str) -> Option<f64> {
+    let value = text.split_once('=')?.1.split_whitespace().next()?;
+    (1..=value.len())
+        .rev()
+        .filter(|&end| value.is_char_boundary(end))
+        .find_map(|end| value[..end].parse().ok())
+}
+
+// Bodies from Horizons tables, one per table at its first state, centered on the barycenter at rest
+// The tables need the same center and start time. The center is added at rest when no table targets
+// it, unless it's a barycenter. Sizes, colors and spins come from the bundled solar system for the
+// bodies it has
+pub fn load_bodies(paths: &[PathBuf]) -> Result<Vec<Planet>, String> {
+    let tables = paths
+        .iter()
+        .map(|path| Ephemeris::load(path))
+        .collect::<Result<Vec<_>, _>>()?;
+    bodies(&tables)
+}
+
+pub fn bodies(tables: &[Ephemeris]) -> Result<Vec<Planet>, String> {
+    let first = tables.first().ok_or("No Horizons tables to load")?;
+    let epoch = first.states[0].julian_date;
+    for (i, table) in tables.iter().enumerate() {
+        if table.center != first.center {
+            return Err(format!(
+                "{} is relative to {} and {} to {}, export every table with the same center",
+                first.target, first.center, table.target, table.center
+            ));
+        }
+        if (table.states[0].julian_date - epoch).abs() > 1e-6 {
+            return Err(format!(
+                "{} starts at JD {} and {} at JD {}, export every table from the same start time",
+                first.target, epoch, table.target, table.states[0].julian_date
+            ));
+        }
+        if tables[..i].iter().any(|other| other.target == table.target) {
+            return Err(format!("{} has more than one table", table.target));
+        }
+    }
+
+    let known = Scenario::SolarSystem.build(0);
+    let template = |name: &str| {
+        known.iter().find(|planet| {
+            planet.name == name || name.split_whitespace().next() == Some(planet.name.as_str())
+        })
+    };
+
+    // Each body with its mass, position and velocity relative to the center
+    let mut bodies = Vec::new();
+    let center_targeted = tables.iter().any(|table| table.target == first.center);
+    if !center_targeted && !first.center.contains("Barycenter") {
+        let center = template(&first.center).ok_or_else(|| {
+            format!(
+                "Unknown center body {}, add a table for it or export relative to the Sun",
+                first.center
+            )
+        })?;
+        let mut planet = center.clone();
+        planet.name = first.center.clone();
+        planet.tidal_lock = None;
+        bodies.push((
+            planet,
+            center.mass() as f64,
+            Vector3::zeros(),
+            Vector3::zeros(),
+        ));
+    }
+    for table in tables {
+        let template = template(&table.target);
+        let mass = table
+            .mass()
+            .or(template.map(|planet| planet.mass() as f64))
+            .unwrap_or(0.0);
+        let radius = table
+            .radius
+            .map(|radius| Units::Si.to_sim(radius * 1000.0, Quantity::Length) as f32)
+            .or(template.map(Planet::radius))
+            .unwrap_or(Units::Si.to_sim(DEFAULT_RADIUS * 1000.0, Quantity::Length) as f32);
+
+        let mut planet = match template {
+            Some(template) => {
+                let mut planet = Planet::new(mass as f32, [0.0; 3], radius, template.color());
+                planet.surface = template.surface;
+                planet.atmosphere = template.atmosphere;
+                planet.ring = template.ring;
+                planet.axial_tilt = template.axial_tilt;
+                planet.rot_vel = template.rot_vel;
+                planet.luminosity = template.luminosity;
+                // Locked to the same partner if it was loaded too
+                planet.tidal_lock = template
+                    .tidal_lock
+                    .map(|target| known[target].name.clone())
+                    .and_then(|partner| tables.iter().position(|table| table.target == partner));
+                planet
+            }
+            None => {
+                let mut planet = Planet::new(mass as f32, [0.0; 3], radius, DEFAULT_COLOR);
+                planet.surface = Surface::new(SurfaceType::Rocky, bodies.len() as u32 * 7919);
+                planet
+            }
+        };
+        planet.name = table.target.clone();
+        let (pos, vel) = table.world_state(&table.states[0]);
+        bodies.push((planet, mass, pos, vel));
+    }
+
+    // Tidal locks were found among the tables, move them past the added center
+    let offset = bodies.len() - tables.len();
+    for (planet, ..) in &mut bodies {
+        planet.tidal_lock = planet.tidal_lock.map(|target| target + offset);
+    }
+
+    let total_mass = bodies.iter().map(|(_, mass, ..)| mass).sum::<f64>();
+    let (mut pos, mut vel) = (Vector3::zeros(), Vector3::zeros());
+    if total_mass > 0.0 {
+        for (_, mass, body_pos, body_vel) in &bodies {
+            pos += body_pos * *mass / total_mass;
+            vel += body_vel * *mass / total_mass;
+        }
+    }
+
+    let mut planets = bodies
+        .into_iter()
+        .map(|(mut planet, _, body_pos, body_vel)| {
+            planet.pos = (body_pos - pos).cast();
+            planet.vel = (body_vel - vel).cast();
+            planet.vel2 = planet.vel;
+            planet
+        })
+        .collect::<Vec<_>>();
+
+    for i in 0..planets.len() {
+        if let Some(target) = planets[i].tidal_lock {
+            let target = planets[target].pos;
+            planets[i].face_towards(target);
+        }
+    }
+    Ok(planets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EARTH: &str = include_str!("../assets/horizons/earth.txt");
+    const EARTH_ICRF: &str = include_str!("../assets/horizons/earth_icrf.txt");
+    const MARS: &str = include_str!("../assets/horizons/mars.txt");
+    const AU_KM: f64 = ASTRONOMICAL_UNIT / 1000.0;
+
+    fn close(a: f64, b: f64, tolerance: f64) -> bool {
+        (a - b).abs() <= tolerance * b.abs()
+    }
+
+    #[test]
+    fn reads_text_tables() {
+        let earth = Ephemeris::parse(EARTH).unwrap();
+        assert_eq!(earth.target, "Earth");
+        assert_eq!(earth.center, "Sun");
+        assert_eq!(earth.gm, Some(398600.435436));
+        assert_eq!(earth.radius, Some(6371.01));
+        assert_eq!(earth.length_unit, 1000.0);
+        assert_eq!(earth.time_unit, 1.0);
+        assert_eq!(earth.plane, ReferencePlane::Ecliptic);
+        assert_eq!(earth.states.len(), 2);
+        assert_eq!(earth.states[0].julian_date, 2451545.0);
+        assert_eq!(earth.states[1].julian_date, 2451546.0);
+        assert_eq!(
+            earth.states[0].pos,
+            Vector3::new(
+                -2.650444161531122E+07,
+                1.446932274612525E+08,
+                -3.866346406764604E+01
+            )
+        );
+        assert_eq!(
+            earth.states[0].vel,
+            Vector3::new(
+                -2.978645521577596E+01,
+                -5.478770160831323E+00,
+                1.463981673260546E-06
+            )
+        );
+
+        // Real exports name the ephemeris a body's state comes from after its name
+        assert_eq!(
+            body_name("Earth (399)                     {source: DE441}"),
+            "Earth"
+        );
+
+        let mars = Ephemeris::parse(MARS).unwrap();
+        assert_eq!(mars.target, "Mars");
+        assert_eq!(mars.gm, Some(42828.375214));
+        assert_eq!(mars.radius, Some(3389.92));
+        assert_eq!(mars.time_unit, 86400.0);
+    }
+
+    #[test]
+    fn reads_csv_tables() {
+        let earth = Ephemeris::parse(EARTH_ICRF).unwrap();
+        assert_eq!(earth.target, "Earth");
+        assert_eq!(earth.length_unit, ASTRONOMICAL_UNIT);
+        assert_eq!(earth.time_unit, 86400.0);
+        assert_eq!(earth.plane, ReferencePlane::Equator);
+        assert_eq!(earth.gm, None);
+        assert_eq!(earth.states.len(), 2);
+        assert_eq!(earth.states[1].julian_date, 2451546.0);
+        assert_eq!(earth.states[1].vel.z, -1.378212313799999E-03);
+    }
+
+    #[test]
+    fn converts_to_simulation_units() {
+        let earth = Ephemeris::parse(EARTH).unwrap();
+        let (pos, vel) = earth.world_state(&earth.states[0]);
+        let au = |value: f64| Units::Astronomical.express(value, Quantity::Length);
+        // The ecliptic's y axis is the world's -z
+        assert!(close(au(pos.x), -2.650444161531122E+07 / AU_KM, 1e-12));
+        assert!(close(au(pos.z), -1.446932274612525E+08 / AU_KM, 1e-12));
+        assert!(close(au(pos.y), -3.866346406764604E+01 / AU_KM, 1e-12));
+        let speed = Units::Si.express(vel.norm(), Quantity::Velocity) / 1000.0;
+        assert!(close(speed, 30.28613274745795, 1e-12));
+
+        let mars = Ephemeris::parse(MARS).unwrap();
+        let (_, vel) = mars.world_state(&mars.states[0]);
+        let speed = Units::Si.express(vel.norm(), Quantity::Velocity) / 1000.0;
+        assert!(close(speed, 26.328005666805677, 1e-12));
+    }
+
+    #[test]
+    fn equator_and_ecliptic_tables_agree() {
+        let ecliptic = Ephemeris::parse(EARTH).unwrap();
+        let equator = Ephemeris::parse(EARTH_ICRF).unwrap();
+        for (a, b) in ecliptic.states.iter().zip(&equator.states) {
+            let (pos, vel) = ecliptic.world_state(a);
+            let (equator_pos, equator_vel) = equator.world_state(b);
+            assert!((pos - equator_pos).norm() < 1e-9 * pos.norm());
+            assert!((vel - equator_vel).norm() < 1e-9 * vel.norm());
+        }
+    }
+
+    #[test]
+    fn builds_bodies_around_the_center() {
+        let tables = [
+            Ephemeris::parse(EARTH).unwrap(),
+            Ephemeris::parse(MARS).unwrap(),
+        ];
+        let planets = bodies(&tables).unwrap();
+        let names = planets.iter().map(|planet| planet.name.as_str());
+        assert_eq!(names.collect::<Vec<_>>(), ["Sun", "Earth", "Mars"]);
+        assert!(planets[0].luminosity > 0.0);
+
+        let mars_mass = Units::Si.express(planets[2].mass() as f64, Quantity::Mass);
+        assert!(close(mars_mass, 6.4171e23, 1e-3));
+        let distance = (planets[2].pos - planets[0].pos).norm() as f64;
+        let distance = Units::Astronomical.express(distance, Quantity::Length);
+        assert!(close(distance, 2.081144414180813E+08 / AU_KM, 1e-6));
+
+        // Centered on the barycenter at rest
+        let momentum = planets
+            .iter()
+            .map(|planet| planet.vel * planet.mass())
+            .sum::<Vector3<f32>>();
+        assert!(momentum.norm() < 1e-5 * (planets[1].vel * planets[1].mass()).norm());
+    }
+
+    #[test]
+    fn reports_problems() {
+        let err = Ephemeris::parse("Target body name: Earth (399)\n").unwrap_err();
+        assert!(err.contains("$$SOE"), "{err}");
+
+        let positions_only = EARTH
+            .lines()
+            .filter(|line| !line.contains("VX="))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let err = Ephemeris::parse(&positions_only).unwrap_err();
+        assert!(err.contains("has no VX"), "{err}");
+
+        let later = MARS.replace("2451545.000000000", "2451544.000000000");
+        let tables = [
+            Ephemeris::parse(EARTH).unwrap(),
+            Ephemeris::parse(&later).unwrap(),
+        ];
+        let err = bodies(&tables).unwrap_err();
+        assert!(err.contains("same start time"), "{err}");
+    }
+}
@@ -1,13 +1,39 @@
+mod benchmark;
+mod bvh;
 mod calculations;
 mod camera;
+mod cli;
+mod clock;
+mod cpu_renderer;
+mod export;
+mod headless;
+mod horizons;
 mod mesh;
+mod orbit;
+mod overlay;
+mod postprocess;
+mod recorder;
 mod renderer;
-
-use calculations::{Planet, RawPlanetData};
-use camera::Camera;
-use nalgebra::Vector3;
-use rand::Rng;
-use renderer::Renderer;
+mod replay;
+mod scenario;
+mod screenshot;
+mod sky;
+mod surface;
+mod ui;
+mod units;
+mod validation;
+
+use calculations::{step_system, Planet, RawPlanetData};
+use camera::{Camera, RawCameraData};
+use cli::Args;
+use clock::SimulationClock;
+use export::{CameraPath, ExportSettings, FrameExporter};
+use overlay::{BodyLabels, OverlaySettings};
+use recorder::{RecorderSettings, TrajectoryRecorder};
+use renderer::{RenderMode, RenderSettings, Renderer};
+use replay::{Recording, ReplayPlayer};
+use screenshot::{CaptureSize, ScreenshotSettings};
+use units::Units;
 use winit::{
     dpi::PhysicalPosition,
     event::{ElementState, Event, WindowEvent},
@@ -15,29 +41,152 @@ use winit::{
     window::WindowBuilder,
 };
 
-const PLANET_ARRAY_SIZE: usize = 5;
-
 // Helper functions
-fn splice_planets(
-    index: usize,
-    planets: &mut [Planet],
-) -> (&mut Planet, Vec<&mut Planet>) {
-    let (pre_planets, post_planets_and_current) = planets.split_at_mut(index);
-    let (this_planet, post_planets) = post_planets_and_current.split_first_mut().unwrap();
-    let chain = pre_planets.iter_mut().chain(post_planets.iter_mut());
-    (this_planet, chain.collect::<Vec<_>>())
+fn planets_to_raw_data(planets: &[Planet]) -> Vec<RawPlanetData> {
+    planets.iter().map(|x| x.to_raw_data()).collect()
+}
+
+// Start or stop recording, errors are reported without interrupting the simulation
+fn toggle_recording(
+    recorder: &mut Option<TrajectoryRecorder>,
+    settings: &RecorderSettings,
+    units: Units,
+    time: f64,
+    planets: &[Planet],
+) {
+    match recorder.take() {
+        Some(active) => match active.finish() {
+            Ok(path) => println!("Saved recording to {}", path.display()),
+            Err(err) => eprintln!("Failed to save recording: {err}"),
+        },
+        None => match TrajectoryRecorder::start(settings, units) {
+            Ok(mut new_recorder) => {
+                println!("Recording to {}", new_recorder.path.display());
+                match new_recorder.record(time, planets) {
+                    Ok(()) => *recorder = Some(new_recorder),
+                    Err(err) => eprintln!("Failed to write recording: {err}"),
+                }
+            }
+            Err(err) => eprintln!("Failed to start recording: {err}"),
+        },
+    }
 }
 
-fn planets_to_raw_data(planets: &[Planet]) -> [RawPlanetData; PLANET_ARRAY_SIZE] {
-    planets
-        .iter()
-        .map(|x| x.to_raw_data())
-        .collect::<Vec<_>>()
-        .try_into()
-        .unwrap()
+// Render the current frame offscreen at the capture size, without the UI
+fn render_capture(renderer: &Renderer, info: SceneInfo, size: CaptureSize) -> image::RgbaImage {
+    // The shader needs the capture resolution for the aspect ratio
+    let capture_info = SceneInfo {
+        resolution: size.render.map(|side| side as f32),
+        ..info
+    };
+    renderer.queue.write_buffer(
+        &renderer.info_buffer,
+        0,
+        bytemuck::cast_slice(&[capture_info]),
+    );
+    let image = renderer.render_to_image(size.render[0], size.render[1]);
+    renderer
+        .queue
+        .write_buffer(&renderer.info_buffer, 0, bytemuck::cast_slice(&[info]));
+
+    screenshot::downsample(image, size)
+}
+
+// Render the current frame offscreen and save it as a PNG
+fn take_screenshot(
+    renderer: &Renderer,
+    settings: &ScreenshotSettings,
+    info: SceneInfo,
+    window_size: [u32; 2],
+    supersampled: bool,
+) {
+    let size = settings.capture_size(window_size, supersampled, renderer.max_texture_size());
+    let image = render_capture(renderer, info, size);
+
+    match screenshot::save(&image, &settings.directory) {
+        Ok(path) => println!("Saved screenshot to {}", path.display()),
+        Err(err) => eprintln!("Failed to save screenshot: {err}"),
+    }
 }
 
 fn main() {
+    let args = match Args::parse() {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            println!("{}", cli::USAGE);
+            return;
+        }
+        Err(err) => {
+            eprintln!("{err}\n\n{}", cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    if args.kepler_report {
+        print!("{}", validation::report());
+        return;
+    }
+
+    let mut planets = if args.horizons.is_empty() {
+        args.scenario.build(args.bodies)
+    } else {
+        horizons::load_bodies(&args.horizons).unwrap_or_else(|err| {
+            eprintln!("{err}");
+            std::process::exit(1);
+        })
+    };
+
+    if args.headless {
+        if let Err(err) = headless::run(&args, &mut planets) {
+            eprintln!("Headless run failed: {err}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let sky_image = sky::load_or_default(args.sky.as_deref()).unwrap_or_else(|err| {
+        eprintln!("{err}");
+        std::process::exit(1);
+    });
+
+    // Replays are fed from the recording file instead of the physics
+    let mut replay = args.replay.as_deref().map(|path| {
+        let recording = Recording::load(path).unwrap_or_else(|err| {
+            eprintln!("{err}");
+            std::process::exit(1);
+        });
+        ReplayPlayer::new(recording)
+    });
+    if let Some(player) = &replay {
+        planets = player.planets();
+    }
+
+    let mut exporter = args.export.as_ref().map(|directory| {
+        let camera_path = match &args.keyframes {
+            Some(path) => CameraPath::load(path).unwrap_or_else(|err| {
+                eprintln!("{err}");
+                std::process::exit(1);
+            }),
+            None => CameraPath::default(),
+        };
+
+        let settings = ExportSettings {
+            directory: directory.clone(),
+            frame_count: args.frames,
+            fps: args.fps,
+            size: args.export_size,
+            supersample: args.supersample,
+            frame_dt: args.frame_dt(),
+            max_step: args.max_step(),
+            camera_path,
+        };
+        FrameExporter::new(settings).unwrap_or_else(|err| {
+            eprintln!("Failed to create {}: {err}", directory.display());
+            std::process::exit(1);
+        })
+    });
+
+    // Window setup happens after headless mode has had a chance to run
     let event_loop = EventLoop::new().unwrap();
 
     let window = WindowBuilder::new()
@@ -50,49 +199,15 @@ fn main() {
         resolution: [800.0; 2],
         delta_time: 1.0,
         planet_count: 1,
+        samples: args.samples,
+        render_mode: args.render_mode as u32,
+        potential_plane: 0,
+        plane_height: 0.0,
+        contour_density: 0.0,
+        padding: 0.0,
     };
 
-    let mut camera = Camera::default();
-    camera.pos = Vector3::new(0.0, 0.0, -200.0);
-    camera.roll = std::f32::consts::FRAC_PI_6;
-
-    // Random number generator
-    let mut rng = rand::thread_rng();
-    let planet_count = 5;
-    let mut planets: [Planet; PLANET_ARRAY_SIZE] = [Planet::default(); PLANET_ARRAY_SIZE];
-
-    (0..planet_count).for_each(|i| {
-        planets[i] = match i {
-            0 => Planet::new(10000.0, [0.0, 0.0, 0.0], 35.0, [1.0, 132.0 / 255.0, 0.0]),
-            _ => Planet::new(
-                rng.gen_range(5.0..15.0),
-                [rng.gen_range(100.0..500.0), 0.0, 0.0],
-                rng.gen_range(5.0..15.0),
-                [
-                    rng.gen_range(0.0..1.0),
-                    rng.gen_range(0.0..1.0),
-                    rng.gen_range(0.0..1.0),
-                ],
-            ),
-        }
-    });
-
-    // Set centripetal acceleration after initialized
-    for i in 1..planet_count {
-        let (this_planet, mut other_planets) = splice_planets(i, &mut planets[..planet_count]);
-        this_planet.set_init_velocity(&mut other_planets);
-    }
-
-    /* (0..planet_count).for_each(|i| {
-        println!("Initial vel: {}", planets[i].vel);
-    }); */
-
-    let mut renderer = Renderer::new(&window, &planets_to_raw_data(&planets), &camera, scene_info);
-
-    event_loop.set_control_flow(winit::event_loop::ControlFlow::Poll);
-
-    let mut mouse_r_pressed = false;
-    let mut pmouse = PhysicalPosition::new(0.0_f64, 0.0); // Previous mouse position
+    let mut camera = Camera::starting_view();
 
     let mut imgui = imgui::Context::create();
     let mut platform = imgui_winit_support::WinitPlatform::init(&mut imgui);
@@ -104,141 +219,425 @@ fn main() {
 
     imgui.set_ini_filename(None);
 
+    let mut renderer = Renderer::new(
+        &window,
+        &planets_to_raw_data(&planets),
+        &camera,
+        scene_info,
+        &sky_image,
+        &mut imgui,
+    );
+    renderer.post_settings = args.post_settings();
+
+    event_loop.set_control_flow(winit::event_loop::ControlFlow::Poll);
+
+    let mut mouse_r_pressed = false;
+    let mut pmouse = PhysicalPosition::new(0.0_f64, 0.0); // Previous mouse position
+
     let mut last_frame_time = std::time::Instant::now();
 
     let mut current_resolution: [f32; 2] = window.inner_size().into();
 
-    camera.set_sensitivity([-1.0, 1.0]);
+    let mut clock = SimulationClock::default();
+    clock.max_step = args.max_step();
+    clock.integrator = args.integrator;
+    let mut render_settings = RenderSettings {
+        samples: args.samples,
+        mode: args.render_mode,
+        potential: args.potential_plane(),
+    };
+    // Smoothed time between frames for the Display window
+    let mut frame_ms = 0.0;
+    let mut mode_comparison = None;
+    let mut comparison_request = false;
+    let mut overlay_settings = OverlaySettings::default();
+    let mut body_labels = BodyLabels::default();
+
+    let mut screenshot_settings = ScreenshotSettings {
+        size: args.screenshot_size,
+        supersample: args.supersample,
+        ..Default::default()
+    };
+    // Set by F12 or the UI, true for a supersampled capture
+    let mut screenshot_request: Option<bool> = None;
+    let mut modifiers = winit::keyboard::ModifiersState::empty();
+
+    let mut units = args.units;
+    let mut recorder_settings = RecorderSettings {
+        format: args.record_format(),
+        interval: args.record_interval,
+        ..Default::default()
+    };
+    let mut recorder = match &args.record {
+        Some(path) => {
+            let mut recorder = TrajectoryRecorder::create(
+                path,
+                recorder_settings.format,
+                args.record_interval,
+                units,
+            )
+            .expect("Failed to create recording");
+            recorder
+                .record(clock.time, &planets)
+                .expect("Failed to write recording");
+            Some(recorder)
+        }
+        None => None,
+    };
 
     event_loop
-        .run(|event, elwt| match event {
-            Event::WindowEvent {
-                event: WindowEvent::CloseRequested,
-                ..
-            } => elwt.exit(),
-            Event::WindowEvent {
-                event: WindowEvent::Resized(new_size),
-                ..
-            } => {
-                renderer.resize(new_size);
-                current_resolution = new_size.into();
-            }
-            Event::AboutToWait => {
-                window.request_redraw();
-            }
-            Event::WindowEvent {
-                event: WindowEvent::RedrawRequested,
-                ..
-            } => {
-                let current_frame_time = std::time::Instant::now();
-                let delta_time = current_frame_time - last_frame_time;
-                let delta_time = delta_time.as_micros() as f32 / 100000.0;
-
-                let raw_camera_data = camera.to_raw_data();
-
-                renderer.queue.write_buffer(
-                    &renderer.camera_buffer,
-                    0,
-                    bytemuck::cast_slice(&[raw_camera_data]),
-                );
-
-                let raw_planet_data = PlanetData {
-                    planets: planets_to_raw_data(&planets),
-                };
-
-                renderer.queue.write_buffer(
-                    &renderer.planet_buffer,
-                    0,
-                    bytemuck::cast_slice(&[raw_planet_data]),
-                );
-
-                let info = SceneInfo {
-                    mouse_pos: pmouse.into(),
-                    resolution: current_resolution,
-                    delta_time,
-                    planet_count: planet_count as u32,
-                };
-                renderer.queue.write_buffer(
-                    &renderer.info_buffer,
-                    0,
-                    bytemuck::cast_slice(&[info]),
-                );
-
-                // Calculate any collisions
-                for i in 0..planet_count {
-                    let (this_planet, mut other_planets) =
-                        splice_planets(i, &mut planets[..planet_count]);
-
-                    this_planet.calc_collision(&mut other_planets);
+        .run(|event, elwt| {
+            platform.handle_event(imgui.io_mut(), &window, &event);
+
+            match event {
+                Event::WindowEvent {
+                    event: WindowEvent::CloseRequested,
+                    ..
+                } => {
+                    if recorder.is_some() {
+                        toggle_recording(
+                            &mut recorder,
+                            &recorder_settings,
+                            units,
+                            clock.time,
+                            &planets,
+                        );
+                    }
+                    elwt.exit();
                 }
-
-                // Frame update for physics
-                for i in 0..planet_count {
-                    let (this_planet, mut other_planets) =
-                        splice_planets(i, &mut planets[..planet_count]);
-
-                    this_planet.step(&mut other_planets, delta_time);
+                Event::WindowEvent {
+                    event: WindowEvent::Resized(new_size),
+                    ..
+                } => {
+                    renderer.resize(new_size);
+                    current_resolution = new_size.into();
                 }
-
-                renderer.render();
-                last_frame_time = current_frame_time;
-            }
-            Event::WindowEvent {
-                event:
-                    WindowEvent::MouseInput {
-                        // button: MouseButton::Right,
-                        state,
-                        ..
-                    },
-                ..
-            } => {
-                mouse_r_pressed = match state {
-                    ElementState::Pressed => true,
-                    ElementState::Released => false,
-                };
-            }
-            Event::WindowEvent {
-                event: WindowEvent::CursorMoved { position, .. },
-                ..
-            } => {
-                if mouse_r_pressed {
-                    let dp: [f64; 2] = [position.x - pmouse.x, position.y - pmouse.y];
-                    camera.rotate_from_mouse(dp);
+                // Dropping an image on the window swaps the sky
+                Event::WindowEvent {
+                    event: WindowEvent::DroppedFile(path),
+                    ..
+                } => match sky::load_image(&path) {
+                    Ok(image) => renderer.set_sky_image(&image),
+                    Err(err) => eprintln!("{err}"),
+                },
+                Event::AboutToWait => {
+                    platform
+                        .prepare_frame(imgui.io_mut(), &window)
+                        .expect("Failed to prepare UI frame");
+                    window.request_redraw();
                 }
-                pmouse = position;
-            }
-            Event::WindowEvent {
-                event: WindowEvent::MouseWheel { delta, .. },
-                ..
-            } => {
-                let dist = match delta {
-                    winit::event::MouseScrollDelta::LineDelta(_, y) => y,
-                    winit::event::MouseScrollDelta::PixelDelta(dist) => dist.y as f32,
-                };
-                camera.scroll(dist);
-            }
-            /* Event::WindowEvent {
-                event:
-                    WindowEvent::KeyboardInput {
-                        event:
-                            winit::event::KeyEvent {
-                                physical_key: winit::keyboard::PhysicalKey::Code(key),
-                                ..
+                Event::WindowEvent {
+                    event: WindowEvent::RedrawRequested,
+                    ..
+                } => {
+                    let current_frame_time = std::time::Instant::now();
+                    let delta_time = current_frame_time - last_frame_time;
+                    imgui.io_mut().update_delta_time(delta_time);
+                    camera.update(delta_time.as_secs_f32());
+                    let frame_time = delta_time.as_secs_f32();
+                    frame_ms = frame_ms * 0.9 + frame_time * 1000.0 * 0.1;
+                    let delta_time = delta_time.as_micros() as f32 / 100000.0;
+
+                    // Exports follow the keyframed camera path if there is one
+                    if let Some((pos, target)) = exporter.as_ref().and_then(|e| e.camera()) {
+                        camera.look_at(pos, target);
+                    }
+
+                    let raw_camera_data = camera.to_raw_data();
+
+                    renderer.queue.write_buffer(
+                        &renderer.camera_buffer,
+                        0,
+                        bytemuck::cast_slice(&[raw_camera_data]),
+                    );
+
+                    renderer.write_planets(&planets_to_raw_data(&planets));
+                    let mut lines = overlay::vector_arrows(&mut planets, overlay_settings);
+                    if overlay_settings.grid {
+                        lines.extend(overlay::reference_grid(raw_camera_data.position()));
+                    }
+                    renderer.set_overlay(raw_camera_data, &lines);
+
+                    let info = SceneInfo {
+                        mouse_pos: pmouse.into(),
+                        resolution: current_resolution,
+                        delta_time,
+                        planet_count: planets.len() as u32,
+                        samples: render_settings.samples,
+                        render_mode: render_settings.mode as u32,
+                        potential_plane: render_settings.potential.visible as u32,
+                        plane_height: render_settings.potential.height,
+                        contour_density: render_settings.potential.contour_density,
+                        padding: 0.0,
+                    };
+                    renderer.queue.write_buffer(
+                        &renderer.info_buffer,
+                        0,
+                        bytemuck::cast_slice(&[info]),
+                    );
+
+                    // Export frames are rendered before the simulation moves on to the next one
+                    if let Some(active) = exporter.as_mut().filter(|e| !e.is_finished()) {
+                        let size = screenshot::supersampled_size(
+                            active.settings.size,
+                            active.settings.supersample,
+                            renderer.max_texture_size(),
+                        );
+                        let image = render_capture(&renderer, info, size);
+                        if let Err(err) = active.save_frame(&image) {
+                            eprintln!("Failed to save frame: {err}");
+                            elwt.exit();
+                        }
+                        window.set_title(&format!(
+                            "Celestial Simulation - exporting frame {}/{}",
+                            active.frame, active.settings.frame_count
+                        ));
+                    }
+
+                    match &mut replay {
+                        Some(player) => {
+                            match &exporter {
+                                Some(active) => player.advance_time(active.settings.frame_dt as f64),
+                                None => player.advance(frame_time),
+                            }
+                            planets = player.planets();
+                        }
+                        None => {
+                            // Exports step a fixed amount of simulated time per frame
+                            let substeps = match &exporter {
+                                Some(active) => active.substeps(),
+                                None => clock.advance(frame_time),
+                            };
+                            for _ in 0..substeps.count {
+                                step_system(&mut planets, substeps.dt, clock.integrator);
+                                clock.time += substeps.dt as f64;
+
+                                if let Some(active) = &mut recorder {
+                                    if let Err(err) = active.record(clock.time, &planets) {
+                                        eprintln!("Failed to write recording, stopping: {err}");
+                                        recorder = None;
+                                    }
+                                }
+                            }
+                        }
+                    }
+
+                    let ui = imgui.new_frame();
+                    let mut recording_toggled = false;
+                    match &mut replay {
+                        Some(player) => ui::replay_controls(ui, player, &mut units),
+                        None => {
+                            ui::time_controls(ui, &mut clock, &mut units);
+                            recording_toggled = ui::recording_controls(
+                                ui,
+                                &mut recorder_settings,
+                                recorder.as_ref(),
+                                units,
+                            );
+                        }
+                    }
+                    if ui::screenshot_controls(
+                        ui,
+                        &mut screenshot_settings,
+                        window.inner_size().into(),
+                    ) {
+                        screenshot_request = Some(true);
+                    }
+                    ui::overlay_controls(ui, &mut overlay_settings, &mut render_settings.potential);
+                    if overlay_settings.labels {
+                        let labels = body_labels.update(
+                            &planets,
+                            &raw_camera_data,
+                            ui.io().display_size,
+                            frame_time,
+                        );
+                        ui::body_labels(ui, &labels);
+                    } else {
+                        body_labels.clear();
+                    }
+                    if overlay_settings.grid {
+                        // The scale is true at the depth of the origin, hidden when it's behind a
+                        // free-fly camera
+                        if let Some(depth) = raw_camera_data.origin_depth() {
+                            let [_, height] = ui.io().display_size;
+                            ui::scale_bar(
+                                ui,
+                                RawCameraData::units_per_pixel(depth, height),
+                                units,
+                            );
+                        }
+                    }
+                    comparison_request |= ui::display_controls(
+                        ui,
+                        &mut renderer.post_settings,
+                        &mut render_settings,
+                        frame_ms,
+                        mode_comparison.as_ref(),
+                    );
+                    platform.prepare_render(ui, &window);
+
+                    if recording_toggled {
+                        toggle_recording(
+                            &mut recorder,
+                            &recorder_settings,
+                            units,
+                            clock.time,
+                            &planets,
+                        );
+                    }
+
+                    if let Some(supersampled) = screenshot_request.take() {
+                        take_screenshot(
+                            &renderer,
+                            &screenshot_settings,
+                            info,
+                            window.inner_size().into(),
+                            supersampled,
+                        );
+                    }
+
+                    if std::mem::take(&mut comparison_request) {
+                        let size = screenshot::supersampled_size(
+                            window.inner_size().into(),
+                            1,
+                            renderer.max_texture_size(),
+                        );
+                        let with_mode = |mode: RenderMode| SceneInfo {
+                            render_mode: mode as u32,
+                            ..info
+                        };
+                        // Timed at the window size in the window's targets, the capture restores info
+                        mode_comparison = Some(benchmark::compare_render_modes(
+                            |mode| {
+                                renderer.queue.write_buffer(
+                                    &renderer.info_buffer,
+                                    0,
+                                    bytemuck::cast_slice(&[with_mode(mode)]),
+                                );
+                                renderer.time_scene_pass()
                             },
-                        ..
-                    },
-                ..
-            } => {
-                let offset = match key {
-                    winit::keyboard::KeyCode::KeyW => Vector3::new(0.0, 0.0, 1.0),
-                    winit::keyboard::KeyCode::KeyS => Vector3::new(0.0, 0.0, -1.0),
-                    winit::keyboard::KeyCode::KeyD => Vector3::new(1.0, 0.0, 0.0),
-                    winit::keyboard::KeyCode::KeyA => Vector3::new(-1.0, 0.0, 0.0),
-                    _ => Vector3::zeros(),
-                };
-                camera.pos += offset * 2.0;
-            } */
-            _ => {}
+                            |mode| render_capture(&renderer, with_mode(mode), size),
+                        ));
+                    }
+
+                    renderer.render(imgui.render());
+
+                    if let Some(active) = exporter.as_ref().filter(|e| e.is_finished()) {
+                        println!(
+                            "Exported {} frames to {}",
+                            active.frame,
+                            active.settings.directory.display()
+                        );
+                        println!(
+                            "Encode with: ffmpeg -framerate {} -i {}/frame_%05d.png -pix_fmt yuv420p out.mp4",
+                            active.settings.fps,
+                            active.settings.directory.display()
+                        );
+                        elwt.exit();
+                    }
+                    last_frame_time = current_frame_time;
+                }
+                Event::WindowEvent {
+                    event:
+                        WindowEvent::MouseInput {
+                            // button: MouseButton::Right,
+                            state,
+                            ..
+                        },
+                    ..
+                } => {
+                    mouse_r_pressed = match state {
+                        ElementState::Pressed => !imgui.io().want_capture_mouse,
+                        ElementState::Released => false,
+                    };
+                }
+                Event::WindowEvent {
+                    event: WindowEvent::CursorMoved { position, .. },
+                    ..
+                } => {
+                    if mouse_r_pressed {
+                        let dp: [f64; 2] = [position.x - pmouse.x, position.y - pmouse.y];
+                        camera.rotate_from_mouse(dp);
+                    }
+                    pmouse = position;
+                }
+                Event::WindowEvent {
+                    event: WindowEvent::ModifiersChanged(new_modifiers),
+                    ..
+                } => {
+                    modifiers = new_modifiers.state();
+                }
+                Event::WindowEvent {
+                    event: WindowEvent::MouseWheel { delta, .. },
+                    ..
+                } if !imgui.io().want_capture_mouse => {
+                    let dist = match delta {
+                        winit::event::MouseScrollDelta::LineDelta(_, y) => y,
+                        winit::event::MouseScrollDelta::PixelDelta(dist) => dist.y as f32,
+                    };
+                    camera.scroll(dist);
+                }
+                Event::WindowEvent {
+                    event:
+                        WindowEvent::KeyboardInput {
+                            event:
+                                winit::event::KeyEvent {
+                                    physical_key: winit::keyboard::PhysicalKey::Code(key),
+                                    state,
+                                    repeat,
+                                    ..
+                                },
+                            ..
+                        },
+                    ..
+                } => {
+                    // Releases always reach the camera so movement keys can't get stuck
+                    if imgui.io().want_capture_keyboard && state.is_pressed() {
+                        return;
+                    }
+
+                    if camera.process_key(key, state.is_pressed()) || !state.is_pressed() || repeat
+                    {
+                        return;
+                    }
+
+                    match key {
+                        winit::keyboard::KeyCode::KeyC => camera.toggle_mode(),
+                        winit::keyboard::KeyCode::KeyV => {
+                            overlay_settings.velocity = !overlay_settings.velocity;
+                        }
+                        winit::keyboard::KeyCode::KeyF => {
+                            overlay_settings.acceleration = !overlay_settings.acceleration;
+                        }
+                        winit::keyboard::KeyCode::KeyG => {
+                            overlay_settings.grid = !overlay_settings.grid;
+                        }
+                        winit::keyboard::KeyCode::KeyN => {
+                            overlay_settings.labels = !overlay_settings.labels;
+                        }
+                        winit::keyboard::KeyCode::KeyP => {
+                            render_settings.potential.visible = !render_settings.potential.visible;
+                        }
+                        winit::keyboard::KeyCode::F12 => {
+                            screenshot_request = Some(modifiers.shift_key());
+                        }
+                        _ if replay.is_some() => {
+                            replay.as_mut().unwrap().process_key(key);
+                        }
+                        winit::keyboard::KeyCode::F5 => toggle_recording(
+                            &mut recorder,
+                            &recorder_settings,
+                            units,
+                            clock.time,
+                            &planets,
+                        ),
+                        _ => {
+                            clock.process_key(key);
+                        }
+                    }
+                }
+                _ => {}
+            }
         })
         .unwrap();
 }
@@ -250,10 +649,12 @@ pub struct SceneInfo {
     resolution: [f32; 2],
     delta_time: f32,
     planet_count: u32,
-}
-
-#[repr(C)]
-#[derive(Clone, Copy, bytemuck::Zeroable, bytemuck::Pod)]
-pub struct PlanetData {
-    planets: [RawPlanetData; PLANET_ARRAY_SIZE],
+    // Rays per pixel for anti-aliasing
+    samples: u32,
+    // RenderMode discriminant
+    render_mode: u32,
+    potential_plane: u32,
+    plane_height: f32,
+    contour_density: f32,
+    padding: f32,
 }
@@ -0,0 +1,117 @@
+use nalgebra::{Rotation3, Vector3};
+
+// Keplerian elements of an elliptic orbit around a parent body, angles in radians
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OrbitalElements {
+    pub semi_major_axis: f64,
+    pub eccentricity: f64,
+    pub inclination: f64,
+    // Longitude of the ascending node
+    pub node: f64,
+    pub argument_of_periapsis: f64,
+    pub mean_anomaly: f64,
+}
+
+impl OrbitalElements {
+    // Position and velocity relative to the parent, in the frame the angles are measured in
+    // mu is G times the mass of both bodies, in the same units as the semi-major axis
+    pub fn to_state_vector(self, mu: f64) -> (Vector3<f64>, Vector3<f64>) {
+        let (a, e) = (self.semi_major_axis, self.eccentricity);
+        let anomaly = eccentric_anomaly(self.mean_anomaly, e);
+        let (sin, cos) = anomaly.sin_cos();
+        let b = a * (1.0 - e * e).sqrt();
+
+        // In the orbital plane, x towards periapsis
+        let mean_motion = (mu / a.powi(3)).sqrt();
+        let rate = mean_motion / (1.0 - e * cos);
+        let pos = Vector3::new(a * (cos - e), b * sin, 0.0);
+        let vel = Vector3::new(-a * sin * rate, b * cos * rate, 0.0);
+
+        let rotation = Rotation3::from_axis_angle(&Vector3::z_axis(), self.node)
+            * Rotation3::from_axis_angle(&Vector3::x_axis(), self.inclination)
+            * Rotation3::from_axis_angle(&Vector3::z_axis(), self.argument_of_periapsis);
+        (rotation * pos, rotation * vel)
+    }
+}
+
+// Solve Kepler's equation M = E - e sin E for the eccentric anomaly E
+pub fn eccentric_anomaly(mean_anomaly: f64, eccentricity: f64) -> f64 {
+    let mean_anomaly = mean_anomaly.rem_euclid(std::f64::consts::TAU);
+    // Starting from pi converges for every eccentricity below 1
+    let mut anomaly = if eccentricity > 0.8 {
+        std::f64::consts::PI
+    } else {
+        mean_anomaly
+    };
+    for _ in 0..50 {
+        let step = (anomaly - eccentricity * anomaly.sin() - mean_anomaly)
+            / (1.0 - eccentricity * anomaly.cos());
+        anomaly -= step;
+        if step.abs() < 1e-14 {
+            break;
+        }
+    }
+    anomaly
+}
+
+// The ecliptic frame has z towards the ecliptic's north pole, the simulation's orbits lie in y = 0
+pub fn ecliptic_to_world(v: Vector3<f64>) -> Vector3<f64> {
+    Vector3::new(v.x, v.z, -v.y)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::units::{Quantity, Units, SOLAR_MASS};
+
+    #[test]
+    fn solves_keplers_equation_at_high_eccentricity() {
+        for eccentricity in [0.0, 0.5, 0.9, 0.99, 0.999] {
+            for i in 0..=1000 {
+                // Includes 0 and 2π, and mean anomalies just past periapsis where the solution is steepest
+                let mean_anomaly = std::f64::consts::TAU * i as f64 / 1000.0;
+                for mean_anomaly in [mean_anomaly, mean_anomaly * 1e-3] {
+                    let anomaly = eccentric_anomaly(mean_anomaly, eccentricity);
+                    let residual = anomaly
+                        - eccentricity * anomaly.sin()
+                        - mean_anomaly.rem_euclid(std::f64::consts::TAU);
+                    assert!(
+                        residual.abs() < 1e-12,
+                        "e {eccentricity} M {mean_anomaly} residual {residual}"
+                    );
+                }
+            }
+        }
+    }
+
+    // Earth's row of assets/solar_system.txt, two days after perihelion at J2000
+    #[test]
+    fn earth_j2000_elements() {
+        let (node, periapsis) = (0.0_f64, 102.93768193_f64.to_radians());
+        let elements = OrbitalElements {
+            semi_major_axis: 1.00000261,
+            eccentricity: 0.01671123,
+            inclination: (-0.00001531_f64).to_radians(),
+            node,
+            argument_of_periapsis: periapsis - node,
+            mean_anomaly: 100.46457166_f64.to_radians() - periapsis,
+        };
+        let units = Units::Astronomical;
+        let mu = units.grav() * (1.0 + 5.9722e24 / SOLAR_MASS);
+        let (pos, vel) = elements.to_state_vector(mu);
+
+        let km_per_s = |speed: f64| {
+            Units::Si.express(units.to_sim(speed, Quantity::Velocity), Quantity::Velocity) / 1000.0
+        };
+        // The mean orbital speed, a circular orbit of the same size
+        let mean_speed = km_per_s((mu / elements.semi_major_axis).sqrt());
+        assert!((mean_speed - 29.78).abs() < 0.01, "{mean_speed} km/s");
+
+        // Near perihelion Earth is a little closer than 1 AU and faster than average
+        let distance = pos.norm();
+        assert!((0.983..0.984).contains(&distance), "{distance} AU");
+        let speed = km_per_s(vel.norm());
+        assert!((30.25..30.33).contains(&speed), "{speed} km/s");
+        assert!(pos.z.abs() < 1e-6);
+    }
+}
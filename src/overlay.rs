@@ -0,0 +1,482 @@
+use nalgebra::Vector3;
+
+use crate::{
+    calculations::{splice_planets, Planet},
+    camera::{RawCameraData, NEAR},
+    postprocess::HDR_FORMAT,
+};
+
+// Arrow length per e-fold of the vector, arrows grow with the log of the magnitude so slow and fast bodies both show
+const VELOCITY_SCALE: f32 = 15.0;
+const ACCELERATION_SCALE: f32 = 40.0;
+
+const VELOCITY_COLOR: [f32; 3] = [0.2, 1.0, 0.4];
+const ACCELERATION_COLOR: [f32; 3] = [1.0, 0.45, 0.15];
+
+const GRID_COLOR: [f32; 3] = [0.35, 0.45, 0.6];
+const AXIS_COLORS: [[f32; 3]; 3] = [[1.0, 0.25, 0.25], [0.3, 1.0, 0.3], [0.3, 0.5, 1.0]];
+// Grid lines on each side of the camera, in the finest spacing
+const GRID_HALF_LINES: i32 = 50;
+// Each grid line is split up so it can fade out towards the edge
+const GRID_SEGMENTS: i32 = 8;
+// The finest grid spacing is between 1 and 10 times this fraction of the camera's distance from the origin
+const GRID_DENSITY: f32 = 0.05;
+// The grid fades out at this many times the camera's distance, which the lines always reach
+const GRID_FADE: f32 = 2.0;
+
+// Laid out like VertexInput in overlay.wgsl, every two vertices make a line
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct LineVertex {
+    pub position: [f32; 3],
+    // Straight alpha, lines fade by blending over the scene
+    pub color: [f32; 4],
+}
+
+// Seconds a label takes to fade in or out
+const LABEL_FADE_TIME: f32 = 0.25;
+// Pixels between the top of a body and its label
+const LABEL_GAP: f32 = 4.0;
+
+// Which overlays are drawn
+#[derive(Debug, Clone, Copy)]
+pub struct OverlaySettings {
+    pub velocity: bool,
+    pub acceleration: bool,
+    // Ecliptic grid, world axes and the scale bar
+    pub grid: bool,
+    pub labels: bool,
+}
+
+impl Default for OverlaySettings {
+    fn default() -> Self {
+        Self {
+            velocity: false,
+            acceleration: false,
+            grid: false,
+            labels: true,
+        }
+    }
+}
+
+fn push_line(lines: &mut Vec<LineVertex>, from: Vector3<f32>, to: Vector3<f32>, color: [f32; 4]) {
+    push_gradient(lines, from, color, to, color);
+}
+
+fn push_gradient(
+    lines: &mut Vec<LineVertex>,
+    from: Vector3<f32>,
+    from_color: [f32; 4],
+    to: Vector3<f32>,
+    to_color: [f32; 4],
+) {
+    lines.push(LineVertex {
+        position: from.into(),
+        color: from_color,
+    });
+    lines.push(LineVertex {
+        position: to.into(),
+        color: to_color,
+    });
+}
+
+// An arrow leaving the body's surface in the direction of vector
+fn push_arrow(
+    lines: &mut Vec<LineVertex>,
+    center: Vector3<f32>,
+    radius: f32,
+    vector: Vector3<f32>,
+    scale: f32,
+    color: [f32; 3],
+) {
+    let magnitude = vector.magnitude();
+    if !magnitude.is_finite() || magnitude <= 0.0 {
+        return;
+    }
+
+    let direction = vector / magnitude;
+    let length = scale * magnitude.ln_1p();
+    let start = center + direction * radius;
+    let end = start + direction * length;
+
+    // Four barbs around the shaft so the head reads from any side
+    let other = if direction.y.abs() < 0.9 {
+        Vector3::y()
+    } else {
+        Vector3::x()
+    };
+    let side = direction.cross(&other).normalize();
+    let up = direction.cross(&side);
+    let head = length * 0.2;
+
+    let color = [color[0], color[1], color[2], 1.0];
+    push_line(lines, start, end, color);
+    for barb in [side, -side, up, -up] {
+        push_line(
+            lines,
+            end,
+            end - direction * head + barb * head * 0.5,
+            color,
+        );
+    }
+}
+
+// Velocity and net gravitational acceleration arrows for every body
+pub fn vector_arrows(planets: &mut [Planet], settings: OverlaySettings) -> Vec<LineVertex> {
+    let mut lines = Vec::new();
+    for i in 0..planets.len() {
+        let (planet, mut other_planets) = splice_planets(i, planets);
+        if settings.velocity {
+            push_arrow(
+                &mut lines,
+                planet.pos,
+                planet.radius(),
+                planet.vel,
+                VELOCITY_SCALE,
+                VELOCITY_COLOR,
+            );
+        }
+        if settings.acceleration {
+            let accel = planet.calc_accel(&mut other_planets);
+            push_arrow(
+                &mut lines,
+                planet.pos,
+                planet.radius(),
+                accel,
+                ACCELERATION_SCALE,
+                ACCELERATION_COLOR,
+            );
+        }
+    }
+    lines
+}
+
+// Spacing of the finest grid lines seen from distance, always a power of 10, and how far through
+// that power the distance is (0 just after switching to it, nearing 1 before the next)
+fn grid_spacing(distance: f32) -> (f32, f32) {
+    let scale = (distance.max(1e-3) * GRID_DENSITY).log10();
+    (10.0_f32.powf(scale.floor()), scale - scale.floor())
+}
+
+// Grid in the y = 0 plane the generated systems orbit in, plus the world axes from the origin
+// Every 10th and 100th line is brighter, and the finest lines fade out as the camera pulls back so
+// the grid looks the same when the spacing steps to the next power of 10
+pub fn reference_grid(camera_pos: Vector3<f32>) -> Vec<LineVertex> {
+    let distance = camera_pos.magnitude();
+    let (spacing, progress) = grid_spacing(distance);
+    let extent = spacing * GRID_HALF_LINES as f32;
+    let center_x = (camera_pos.x / spacing).round() as i64;
+    let center_z = (camera_pos.z / spacing).round() as i64;
+
+    let alpha = |index: i64, point: Vector3<f32>| {
+        let level = (index % 10 == 0) as i32 + (index % 100 == 0) as i32;
+        let emphasis = ((level as f32 - progress + 1.0) / 2.0).clamp(0.0, 1.0);
+        let offset = ((point.x - camera_pos.x).powi(2) + (point.z - camera_pos.z).powi(2)).sqrt();
+        let edge = (1.0 - offset / (distance * GRID_FADE)).max(0.0);
+        emphasis * edge * 0.8
+    };
+
+    let [r, g, b] = GRID_COLOR;
+    let mut lines = Vec::new();
+    let mut grid_line = |index: i64, start: Vector3<f32>, end: Vector3<f32>| {
+        let point = |t: i32| start.lerp(&end, t as f32 / (2 * GRID_SEGMENTS) as f32);
+        for t in 0..2 * GRID_SEGMENTS {
+            let (from, to) = (point(t), point(t + 1));
+            let from_color = [r, g, b, alpha(index, from)];
+            push_gradient(
+                &mut lines,
+                from,
+                from_color,
+                to,
+                [r, g, b, alpha(index, to)],
+            );
+        }
+    };
+    let (x0, z0) = (center_x as f32 * spacing, center_z as f32 * spacing);
+    for i in -GRID_HALF_LINES as i64..=GRID_HALF_LINES as i64 {
+        let x = (center_x + i) as f32 * spacing;
+        grid_line(
+            center_x + i,
+            Vector3::new(x, 0.0, z0 - extent),
+            Vector3::new(x, 0.0, z0 + extent),
+        );
+        let z = (center_z + i) as f32 * spacing;
+        grid_line(
+            center_z + i,
+            Vector3::new(x0 - extent, 0.0, z),
+            Vector3::new(x0 + extent, 0.0, z),
+        );
+    }
+
+    // Drawn last so they cover the grid lines through the origin
+    for (axis, [r, g, b]) in AXIS_COLORS.into_iter().enumerate() {
+        let mut direction = Vector3::zeros();
+        direction[axis] = extent;
+        push_line(&mut lines, Vector3::zeros(), direction, [r, g, b, 1.0]);
+    }
+    lines
+}
+
+// The longest 1, 2 or 5 times a power of 10 that fits in max_length
+pub fn scale_bar_length(max_length: f64) -> f64 {
+    let power = 10.0_f64.powf(max_length.log10().floor());
+    [5.0, 2.0, 1.0]
+        .into_iter()
+        .map(|step| step * power)
+        .find(|&length| length <= max_length)
+        .unwrap_or(power)
+}
+
+// A body's name on screen, anchored at the bottom center of the text
+#[derive(Debug, Clone)]
+pub struct Label {
+    pub text: String,
+    // In pixels from the top left of the screen
+    pub position: [f32; 2],
+    pub alpha: f32,
+}
+
+// Whether anything is in front of planet as seen from camera_pos, bodies are treated as spheres
+fn is_occluded(planets: &[Planet], index: usize, camera_pos: Vector3<f32>) -> bool {
+    let to_body = planets[index].pos - camera_pos;
+    let distance = to_body.magnitude();
+    if distance <= planets[index].radius() {
+        return true;
+    }
+    let direction = to_body / distance;
+    // Only the stretch of the ray in front of the body's surface can hide it
+    let end = distance - planets[index].radius();
+
+    planets.iter().enumerate().any(|(i, other)| {
+        let to_other = other.pos - camera_pos;
+        let t = to_other.dot(&direction).clamp(0.0, end);
+        i != index && (to_other - direction * t).magnitude() < other.radius()
+    })
+}
+
+// Names drawn over the bodies, fading in and out as they come into view or get hidden
+#[derive(Debug, Default)]
+pub struct BodyLabels {
+    // Per body, by index
+    alphas: Vec<f32>,
+}
+
+impl BodyLabels {
+    // Labels for this frame seen from camera on a screen of size pixels, dt in seconds
+    pub fn update(
+        &mut self,
+        planets: &[Planet],
+        camera: &RawCameraData,
+        size: [f32; 2],
+        dt: f32,
+    ) -> Vec<Label> {
+        self.alphas.resize(planets.len(), 0.0);
+        let [width, height] = size;
+        let view_proj = camera.view_projection(width / height);
+        let camera_pos = camera.position();
+
+        let mut labels = Vec::new();
+        for (i, planet) in planets.iter().enumerate() {
+            let clip = view_proj * planet.pos.push(1.0);
+            let ndc = clip.xy() / clip.w;
+            let on_screen = clip.w > NEAR && ndc.x.abs() <= 1.0 && ndc.y.abs() <= 1.0;
+            let visible = on_screen && !is_occluded(planets, i, camera_pos);
+
+            let step = dt / LABEL_FADE_TIME;
+            let alpha = &mut self.alphas[i];
+            *alpha = (*alpha + if visible { step } else { -step }).clamp(0.0, 1.0);
+            // Labels fading out behind the camera would be projected mirrored
+            if *alpha <= 0.0 || clip.w <= NEAR || planet.name.is_empty() {
+                continue;
+            }
+
+            let radius = planet.radius() / RawCameraData::units_per_pixel(clip.w, height);
+            labels.push(Label {
+                text: planet.name.clone(),
+                position: [
+                    (ndc.x * 0.5 + 0.5) * width,
+                    (0.5 - ndc.y * 0.5) * height - radius - LABEL_GAP,
+                ],
+                alpha: *alpha,
+            });
+        }
+        labels
+    }
+
+    // Start every label from hidden, so they fade in when shown again
+    pub fn clear(&mut self) {
+        self.alphas.clear();
+    }
+}
+
+// Line geometry drawn into the HDR target after the scene, before bloom and tone mapping
+pub struct Overlay {
+    pipeline: wgpu::RenderPipeline,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    vertex_buffer: wgpu::Buffer,
+    vertex_count: u32,
+    camera: Option<RawCameraData>,
+}
+
+impl Overlay {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Overlay Bind Group Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Overlay Uniform Buffer"),
+            size: std::mem::size_of::<[[f32; 4]; 4]>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Overlay Bind Group"),
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(uniform_buffer.as_entire_buffer_binding()),
+            }],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Overlay Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgpu::include_wgsl!("../shaders/overlay.wgsl"));
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Overlay Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                compilation_options: Default::default(),
+                buffers: &[wgpu::VertexBufferLayout {
+                    array_stride: std::mem::size_of::<LineVertex>() as wgpu::BufferAddress,
+                    step_mode: wgpu::VertexStepMode::Vertex,
+                    attributes: &wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x4],
+                }],
+            },
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::LineList,
+                ..Default::default()
+            },
+            // Hidden behind bodies, but never written so lines don't hide each other
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: wgpu::TextureFormat::Depth32Float,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                compilation_options: Default::default(),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: HDR_FORMAT,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            multiview: None,
+            cache: None,
+        });
+
+        Self {
+            pipeline,
+            uniform_buffer,
+            bind_group,
+            vertex_buffer: Self::create_vertex_buffer(device, 0),
+            vertex_count: 0,
+            camera: None,
+        }
+    }
+
+    fn create_vertex_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Overlay Vertex Buffer"),
+            size: (capacity.max(1) * std::mem::size_of::<LineVertex>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    // Replace the lines, seen from camera, the buffer grows when they don't fit
+    pub fn set_lines(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        camera: RawCameraData,
+        lines: &[LineVertex],
+    ) {
+        let capacity = self.vertex_buffer.size() as usize / std::mem::size_of::<LineVertex>();
+        if lines.len() > capacity {
+            self.vertex_buffer =
+                Self::create_vertex_buffer(device, lines.len().next_power_of_two());
+        }
+        if !lines.is_empty() {
+            queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(lines));
+        }
+        self.vertex_count = lines.len() as u32;
+        self.camera = Some(camera);
+    }
+
+    // Draw the lines over color_view, aspect is width over height of the target
+    pub fn encode(
+        &self,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        color_view: &wgpu::TextureView,
+        depth_view: &wgpu::TextureView,
+        aspect: f32,
+    ) {
+        let Some(camera) = self.camera.filter(|_| self.vertex_count > 0) else {
+            return;
+        };
+        let view_proj: [[f32; 4]; 4] = camera.view_projection(aspect).into();
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[view_proj]));
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Overlay Render Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: color_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.draw(0..self.vertex_count, 0..1);
+    }
+}
@@ -0,0 +1,361 @@
+use nalgebra::Vector3;
+
+// The scene is drawn in this format so bright stars can go past 1 before tone mapping
+pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+// Blur passes over the bloom target, more gives a wider glow
+const BLUR_ITERATIONS: usize = 2;
+
+// Discriminants must match post.wgsl
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ToneMapping {
+    // Clamps, anything brighter than 1 clips
+    None = 0,
+    Reinhard = 1,
+    #[default]
+    Aces = 2,
+}
+
+impl ToneMapping {
+    pub const ALL: [ToneMapping; 3] = [ToneMapping::None, ToneMapping::Reinhard, ToneMapping::Aces];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "none" => Some(ToneMapping::None),
+            "reinhard" => Some(ToneMapping::Reinhard),
+            "aces" => Some(ToneMapping::Aces),
+            _ => None,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ToneMapping::None => "None",
+            ToneMapping::Reinhard => "Reinhard",
+            ToneMapping::Aces => "ACES",
+        }
+    }
+
+    // Same curves as post.wgsl, for the CPU renderer
+    pub fn apply(self, color: Vector3<f32>) -> Vector3<f32> {
+        match self {
+            ToneMapping::None => color.map(|x| x.clamp(0.0, 1.0)),
+            ToneMapping::Reinhard => color.map(|x| x / (1.0 + x)),
+            ToneMapping::Aces => color.map(|x| {
+                ((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)).clamp(0.0, 1.0)
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PostSettings {
+    pub tone_mapping: ToneMapping,
+    // Multiplies the scene before tone mapping
+    pub exposure: f32,
+    pub bloom_strength: f32,
+    // Only colors brighter than this glow
+    pub bloom_threshold: f32,
+}
+
+impl Default for PostSettings {
+    fn default() -> Self {
+        Self {
+            tone_mapping: ToneMapping::default(),
+            exposure: 1.0,
+            bloom_strength: 0.6,
+            bloom_threshold: 1.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct PostUniforms {
+    exposure: f32,
+    bloom_strength: f32,
+    bloom_threshold: f32,
+    tone_mapping: u32,
+    blur_direction: [f32; 2],
+    padding: [f32; 2],
+}
+
+impl PostUniforms {
+    fn new(settings: &PostSettings, blur_direction: [f32; 2]) -> Self {
+        Self {
+            exposure: settings.exposure,
+            bloom_strength: settings.bloom_strength,
+            bloom_threshold: settings.bloom_threshold,
+            tone_mapping: settings.tone_mapping as u32,
+            blur_direction,
+            padding: [0.0; 2],
+        }
+    }
+}
+
+// Bloom and tone mapping, run after the scene is drawn into an HDR target
+pub struct PostProcess {
+    bind_group_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    settings_buffer: wgpu::Buffer,
+    blur_buffers: [wgpu::Buffer; 2],
+    bright_pipeline: wgpu::RenderPipeline,
+    blur_pipeline: wgpu::RenderPipeline,
+    tonemap_pipeline: wgpu::RenderPipeline,
+}
+
+// The size dependent textures, one set for the window and one per offscreen capture
+pub struct PostTargets {
+    pub hdr_view: wgpu::TextureView,
+    // Ping-pong pair at half size, the result ends up in the first
+    bloom_views: [wgpu::TextureView; 2],
+    bright_bind_group: wgpu::BindGroup,
+    blur_bind_groups: [wgpu::BindGroup; 2],
+    tonemap_bind_group: wgpu::BindGroup,
+}
+
+impl PostProcess {
+    pub fn new(device: &wgpu::Device, output_format: wgpu::TextureFormat) -> Self {
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Post Bind Group Layout"),
+            entries: &[
+                texture_entry(0),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                texture_entry(3),
+            ],
+        });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Post Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let create_uniform_buffer = |label| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size: std::mem::size_of::<PostUniforms>() as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            })
+        };
+        let settings_buffer = create_uniform_buffer("Post Settings Buffer");
+        let blur_buffers = [
+            create_uniform_buffer("Horizontal Blur Buffer"),
+            create_uniform_buffer("Vertical Blur Buffer"),
+        ];
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Post Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgpu::include_wgsl!("../shaders/post.wgsl"));
+
+        let create_pipeline = |label, entry_point, format| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    compilation_options: Default::default(),
+                    buffers: &[],
+                },
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point,
+                    compilation_options: Default::default(),
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format,
+                        blend: None,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                multiview: None,
+                cache: None,
+            })
+        };
+
+        Self {
+            bright_pipeline: create_pipeline("Bright Pass Pipeline", "bright_pass", HDR_FORMAT),
+            blur_pipeline: create_pipeline("Blur Pipeline", "blur", HDR_FORMAT),
+            tonemap_pipeline: create_pipeline("Tone Map Pipeline", "tonemap", output_format),
+            bind_group_layout,
+            sampler,
+            settings_buffer,
+            blur_buffers,
+        }
+    }
+
+    pub fn create_targets(&self, device: &wgpu::Device, width: u32, height: u32) -> PostTargets {
+        let create_texture = |label, width: u32, height: u32| {
+            device
+                .create_texture(&wgpu::TextureDescriptor {
+                    label: Some(label),
+                    size: wgpu::Extent3d {
+                        width: width.max(1),
+                        height: height.max(1),
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: HDR_FORMAT,
+                    usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                        | wgpu::TextureUsages::TEXTURE_BINDING,
+                    view_formats: &[],
+                })
+                .create_view(&wgpu::TextureViewDescriptor::default())
+        };
+
+        let hdr_view = create_texture("HDR Texture", width, height);
+        let bloom_views = [
+            create_texture("Bloom Texture A", width / 2, height / 2),
+            create_texture("Bloom Texture B", width / 2, height / 2),
+        ];
+
+        let create_bind_group = |source: &wgpu::TextureView, buffer: &wgpu::Buffer, bloom| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Post Bind Group"),
+                layout: &self.bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(source),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&self.sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: wgpu::BindingResource::TextureView(bloom),
+                    },
+                ],
+            })
+        };
+
+        // Passes that don't read the bloom texture bind their source there as well
+        PostTargets {
+            bright_bind_group: create_bind_group(&hdr_view, &self.settings_buffer, &hdr_view),
+            blur_bind_groups: [
+                create_bind_group(&bloom_views[0], &self.blur_buffers[0], &bloom_views[0]),
+                create_bind_group(&bloom_views[1], &self.blur_buffers[1], &bloom_views[1]),
+            ],
+            tonemap_bind_group: create_bind_group(
+                &hdr_view,
+                &self.settings_buffer,
+                &bloom_views[0],
+            ),
+            hdr_view,
+            bloom_views,
+        }
+    }
+
+    pub fn write_settings(&self, queue: &wgpu::Queue, settings: &PostSettings) {
+        queue.write_buffer(
+            &self.settings_buffer,
+            0,
+            bytemuck::cast_slice(&[PostUniforms::new(settings, [0.0; 2])]),
+        );
+        for (buffer, direction) in self.blur_buffers.iter().zip([[1.0, 0.0], [0.0, 1.0]]) {
+            queue.write_buffer(
+                buffer,
+                0,
+                bytemuck::cast_slice(&[PostUniforms::new(settings, direction)]),
+            );
+        }
+    }
+
+    // Bright pass, blur and tone map from the targets' HDR texture into output_view
+    pub fn encode(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        targets: &PostTargets,
+        output_view: &wgpu::TextureView,
+    ) {
+        let mut pass = |label, pipeline, bind_group, view| {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some(label),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            render_pass.set_pipeline(pipeline);
+            render_pass.set_bind_group(0, bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        };
+
+        pass(
+            "Bright Pass",
+            &self.bright_pipeline,
+            &targets.bright_bind_group,
+            &targets.bloom_views[0],
+        );
+        for _ in 0..BLUR_ITERATIONS {
+            pass(
+                "Horizontal Blur Pass",
+                &self.blur_pipeline,
+                &targets.blur_bind_groups[0],
+                &targets.bloom_views[1],
+            );
+            pass(
+                "Vertical Blur Pass",
+                &self.blur_pipeline,
+                &targets.blur_bind_groups[1],
+                &targets.bloom_views[0],
+            );
+        }
+        pass(
+            "Tone Map Pass",
+            &self.tonemap_pipeline,
+            &targets.tonemap_bind_group,
+            output_view,
+        );
+    }
+}
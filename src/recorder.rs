@@ -0,0 +1,435 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use nalgebra::Vector3;
+
+use crate::{
+    calculations::Planet,
+    surface::{Atmosphere, Ring},
+    units::{Quantity, Units},
+};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum RecordFormat {
+    #[default]
+    Csv,
+    JsonLines,
+}
+
+impl RecordFormat {
+    pub const ALL: [RecordFormat; 2] = [RecordFormat::Csv, RecordFormat::JsonLines];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "csv" => Some(Self::Csv),
+            "jsonl" | "json-lines" | "jsonlines" | "ndjson" => Some(Self::JsonLines),
+            _ => None,
+        }
+    }
+
+    // Guess the format from a file extension
+    pub fn from_path(path: &Path) -> Option<Self> {
+        path.extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(Self::from_name)
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::JsonLines => "jsonl",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Csv => "CSV",
+            Self::JsonLines => "JSON Lines",
+        }
+    }
+}
+
+// Settings used when a recording is started from inside the app
+#[derive(Debug, Clone)]
+pub struct RecorderSettings {
+    pub format: RecordFormat,
+    pub interval: f64, // Simulated time between samples
+    pub directory: PathBuf,
+}
+
+impl Default for RecorderSettings {
+    fn default() -> Self {
+        Self {
+            format: RecordFormat::Csv,
+            interval: 1.0,
+            directory: PathBuf::from("recordings"),
+        }
+    }
+}
+
+// Streams the state of every body to a file in long format, one row per body per sample
+// Radius and color are written too so the file can be replayed in the viewer
+// CSV files name their units in a comment on the first line, JSON Lines keys carry them, like x_AU
+pub struct TrajectoryRecorder {
+    writer: BufWriter<File>,
+    format: RecordFormat,
+    units: Units,
+    interval: f64,
+    // Samples are taken as the clock passes multiples of interval from the first one, in either direction
+    first_sample_time: Option<f64>,
+    last_sample_index: i64,
+    pub path: PathBuf,
+    pub samples: u64,
+}
+
+impl TrajectoryRecorder {
+    pub fn create(
+        path: &Path,
+        format: RecordFormat,
+        interval: f64,
+        units: Units,
+    ) -> io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let mut writer = BufWriter::new(File::create(path)?);
+        let [length, mass, time] =
+            [Quantity::Length, Quantity::Mass, Quantity::Time].map(|q| units.unit(q));
+        match format {
+            RecordFormat::Csv => {
+                writeln!(
+                    writer,
+                    "# units: {} (length {length}, mass {mass}, time {time})",
+                    units.name()
+                )?;
+                let columns = body_columns(&Planet::default(), units).map(|(name, _, _)| name);
+                writeln!(writer, "time,body,name,{}", columns.join(","))?;
+            }
+            RecordFormat::JsonLines => {}
+        }
+
+        Ok(Self {
+            writer,
+            format,
+            units,
+            interval: interval.max(0.0),
+            first_sample_time: None,
+            last_sample_index: 0,
+            path: path.to_path_buf(),
+            samples: 0,
+        })
+    }
+
+    // Start a new recording with a timestamped file name in the settings directory
+    pub fn start(settings: &RecorderSettings, units: Units) -> io::Result<Self> {
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default();
+        let path = settings.directory.join(format!(
+            "trajectory_{timestamp}.{}",
+            settings.format.extension()
+        ));
+
+        Self::create(&path, settings.format, settings.interval, units)
+    }
+
+    // Write a sample if the clock has reached the next multiple of the interval, or the previous one
+    // when time runs backwards. Samples stay on that grid however the steps fall
+    pub fn record(&mut self, time: f64, planets: &[Planet]) -> io::Result<()> {
+        match self.first_sample_time {
+            Some(first_sample_time) if self.interval > 0.0 => {
+                let position = (time - first_sample_time) / self.interval;
+                // Small tolerance so float error in the accumulated time doesn't skip a sample
+                let index = if position >= self.last_sample_index as f64 {
+                    (position + 1e-6).floor()
+                } else {
+                    (position - 1e-6).ceil()
+                } as i64;
+                if index == self.last_sample_index {
+                    return Ok(());
+                }
+                self.last_sample_index = index;
+            }
+            Some(_) => {}
+            None => self.first_sample_time = Some(time),
+        }
+
+        let recorded_time = number(self.units.express(time, Quantity::Time));
+        for (index, planet) in planets.iter().enumerate() {
+            let columns = body_columns(planet, self.units);
+            match self.format {
+                RecordFormat::Csv => {
+                    let values = columns.map(|(_, _, value)| number(value));
+                    writeln!(
+                        self.writer,
+                        "{recorded_time},{index},{},{}",
+                        csv_field(&planet.name),
+                        values.join(",")
+                    )?
+                }
+                RecordFormat::JsonLines => {
+                    let fields = columns.map(|(name, quantity, value)| match quantity {
+                        Some(quantity) => format!(
+                            "\"{name}_{}\":{}",
+                            self.units.key(quantity),
+                            json_number(value)
+                        ),
+                        None => format!("\"{name}\":{}", json_number(value)),
+                    });
+                    writeln!(
+                        self.writer,
+                        "{{\"time_{}\":{recorded_time},\"body\":{index},\"name\":{},{}}}",
+                        self.units.key(Quantity::Time),
+                        json_string(&planet.name),
+                        fields.join(",")
+                    )?
+                }
+            }
+        }
+
+        self.samples += 1;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<PathBuf> {
+        self.writer.flush()?;
+        Ok(self.path)
+    }
+}
+
+const BODY_COLUMNS: usize = 34;
+
+// Everything written for a body after its time, index and name: the column, the quantity its unit
+// comes from if it has one and the value in units. The render settings are written so replays look
+// like the run, with an atmosphere height, ring radius, day or lock of 0, 0 or -1 meaning none
+fn body_columns(
+    planet: &Planet,
+    units: Units,
+) -> [(&'static str, Option<Quantity>, f64); BODY_COLUMNS] {
+    let length = Some(Quantity::Length);
+    let convert = |value: f32, quantity| units.express(value as f64, quantity);
+    let pos = planet.pos.map(|v| convert(v, Quantity::Length));
+    let vel = planet.vel.map(|v| convert(v, Quantity::Velocity));
+    let [r, g, b] = planet.color().map(f64::from);
+    let day = match planet.rot_vel {
+        0.0 => 0.0,
+        rot_vel => units.express(std::f64::consts::TAU / rot_vel as f64, Quantity::Time),
+    };
+    let lock = planet.tidal_lock.map_or(-1.0, |target| target as f64);
+    let atmosphere = planet.atmosphere.unwrap_or(Atmosphere {
+        height: 0.0,
+        density: 0.0,
+        color: [0.0; 3],
+    });
+    let ring = planet.ring.unwrap_or(Ring {
+        inner_radius: 0.0,
+        outer_radius: 0.0,
+        normal: Vector3::zeros(),
+        color: [0.0; 3],
+        opacity: 0.0,
+    });
+
+    [
+        ("x", length, pos.x),
+        ("y", length, pos.y),
+        ("z", length, pos.z),
+        ("vx", Some(Quantity::Velocity), vel.x),
+        ("vy", Some(Quantity::Velocity), vel.y),
+        ("vz", Some(Quantity::Velocity), vel.z),
+        (
+            "mass",
+            Some(Quantity::Mass),
+            convert(planet.mass(), Quantity::Mass),
+        ),
+        ("radius", length, convert(planet.radius(), Quantity::Length)),
+        ("r", None, r),
+        ("g", None, g),
+        ("b", None, b),
+        ("luminosity", None, planet.luminosity.into()),
+        ("surface", None, planet.surface.kind as u32 as f64),
+        ("seed", None, planet.surface.seed.into()),
+        ("feature_scale", None, planet.surface.feature_scale.into()),
+        ("displacement", None, planet.surface.displacement.into()),
+        ("tilt", None, planet.axial_tilt.into()),
+        ("spin", None, planet.spin_angle.into()),
+        ("day", Some(Quantity::Time), day),
+        ("lock", None, lock),
+        ("atmosphere_height", None, atmosphere.height.into()),
+        ("atmosphere_density", None, atmosphere.density.into()),
+        ("atmosphere_r", None, atmosphere.color[0].into()),
+        ("atmosphere_g", None, atmosphere.color[1].into()),
+        ("atmosphere_b", None, atmosphere.color[2].into()),
+        ("ring_inner", None, ring.inner_radius.into()),
+        ("ring_outer", None, ring.outer_radius.into()),
+        ("ring_normal_x", None, ring.normal.x.into()),
+        ("ring_normal_y", None, ring.normal.y.into()),
+        ("ring_normal_z", None, ring.normal.z.into()),
+        ("ring_r", None, ring.color[0].into()),
+        ("ring_g", None, ring.color[1].into()),
+        ("ring_b", None, ring.color[2].into()),
+        ("ring_opacity", None, ring.opacity.into()),
+    ]
+}
+
+// Values that came from an f32 are written as one, so 0.85 isn't 0.8500000238418579
+fn number(value: f64) -> String {
+    if value as f32 as f64 == value {
+        (value as f32).to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+// JSON has no NaN or infinity, so bodies that blew up are written as null
+fn json_number(value: f64) -> String {
+    if value.is_finite() {
+        number(value)
+    } else {
+        "null".to_string()
+    }
+}
+
+// Quoted when it holds a comma or quote, with quotes doubled. Rows are one line each, so line breaks become spaces
+fn csv_field(text: &str) -> String {
+    let text = text.replace(['\n', '\r'], " ");
+    if text.contains([',', '"']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text
+    }
+}
+
+fn json_string(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len() + 2);
+    escaped.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f64 = 0.3;
+
+    fn planets() -> Vec<Planet> {
+        vec![
+            Planet::new(10000.0, [0.0; 3], 35.0, [1.0; 3]),
+            Planet::new(10.0, [100.0, 0.0, 0.0], 5.0, [0.5; 3]),
+        ]
+    }
+
+    // Record the times given to the recorder, returns the recorded time of every row
+    fn record(name: &str, format: RecordFormat, times: impl Iterator<Item = f64>) -> Vec<f64> {
+        let path = std::env::temp_dir().join(format!(
+            "recorder_{name}_{}.{}",
+            std::process::id(),
+            format.extension()
+        ));
+        let mut recorder =
+            TrajectoryRecorder::create(&path, format, 1.0, Units::Simulation).unwrap();
+        for time in times {
+            recorder.record(time, &planets()).unwrap();
+        }
+        let samples = recorder.samples;
+        recorder.finish().unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let rows = contents
+            .lines()
+            .filter(|line| !line.starts_with('#') && !line.starts_with("time,"))
+            .map(|line| match format {
+                RecordFormat::Csv => line.split(',').next().unwrap().parse().unwrap(),
+                RecordFormat::JsonLines => {
+                    let (_, rest) = line.split_once("\"time_sim\":").unwrap();
+                    rest.split(',').next().unwrap().parse().unwrap()
+                }
+            })
+            .collect::<Vec<f64>>();
+        assert_eq!(rows.len() as u64, samples * 2);
+        rows
+    }
+
+    // Times of each sample, every body has a row per sample
+    fn sample_times(rows: &[f64]) -> Vec<f64> {
+        rows.chunks(2)
+            .map(|pair| {
+                assert_eq!(pair[0], pair[1]);
+                pair[0]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn samples_stay_on_the_interval_grid() {
+        for format in RecordFormat::ALL {
+            // 100 time units in steps of 0.3 with an interval of 1
+            let times = (0..=333).map(|step| step as f64 * DT);
+            let samples = sample_times(&record("grid", format, times));
+            assert_eq!(samples.len(), 100, "{format:?}");
+            for (k, time) in samples.iter().enumerate() {
+                // The first step at or after each multiple of the interval
+                assert!(
+                    (k as f64..k as f64 + DT).contains(time),
+                    "{format:?} sample {k} at {time}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn samples_when_time_runs_backwards() {
+        // Forwards to 9.9, then back to 0
+        let forwards = (0..=33).map(|step| step as f64 * DT);
+        let backwards = (0..33).rev().map(|step| step as f64 * DT);
+        let samples = sample_times(&record(
+            "reverse",
+            RecordFormat::Csv,
+            forwards.chain(backwards),
+        ));
+
+        assert_eq!(samples.len(), 10 + 9);
+        for (k, time) in samples[10..].iter().enumerate() {
+            // The first step at or before 8, 7, ... 0
+            let grid = 8.0 - k as f64;
+            assert!(
+                *time <= grid + 1e-9 && *time > grid - DT,
+                "sample at {time}"
+            );
+        }
+    }
+
+    #[test]
+    fn json_lines_write_null_for_values_that_are_not_finite() {
+        let path = std::env::temp_dir().join(format!("recorder_nan_{}.jsonl", std::process::id()));
+        let mut planets = planets();
+        planets[1].pos.x = f32::NAN;
+        planets[1].vel.z = f32::INFINITY;
+        let mut recorder =
+            TrajectoryRecorder::create(&path, RecordFormat::JsonLines, 1.0, Units::Simulation)
+                .unwrap();
+        recorder.record(0.0, &planets).unwrap();
+        recorder.finish().unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let row = contents.lines().nth(1).unwrap();
+        assert!(row.contains("\"x_sim\":null"), "{row}");
+        assert!(row.contains("\"vz_sim\":null"), "{row}");
+        assert!(!row.contains("NaN") && !row.contains("inf"), "{row}");
+    }
+}
@@ -1,14 +1,72 @@
-use image::GenericImageView;
-use wgpu::{util::{DeviceExt, RenderEncoder}, Backends, Instance, InstanceDescriptor, RequestAdapterOptions};
+use wgpu::{util::DeviceExt, Backends, Instance, InstanceDescriptor, RequestAdapterOptions};
 
 use crate::{
+    bvh::{self, BvhNode},
     calculations::RawPlanetData,
-    camera::Camera,
+    camera::{Camera, RawCameraData},
     mesh::{INDICES, VERTICES},
-    SceneInfo,
+    overlay::{LineVertex, Overlay},
+    postprocess::{PostProcess, PostSettings, PostTargets, HDR_FORMAT},
+    sky, SceneInfo,
 };
 
-#[derive(Debug)]
+// How fragment.wgsl finds what a ray hits, the discriminants are what the shader sees
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RenderMode {
+    // Sphere tracing the distance field
+    #[default]
+    RayMarch = 0,
+    // Intersecting the bounding spheres directly, only terrain is still marched
+    Analytic = 1,
+}
+
+impl RenderMode {
+    pub const ALL: [RenderMode; 2] = [RenderMode::RayMarch, RenderMode::Analytic];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "march" | "ray-march" => Some(RenderMode::RayMarch),
+            "analytic" => Some(RenderMode::Analytic),
+            _ => None,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            RenderMode::RayMarch => "Ray march",
+            RenderMode::Analytic => "Analytic",
+        }
+    }
+}
+
+// Horizontal reference plane colored by the gravitational potential, with contour lines
+#[derive(Debug, Clone, Copy)]
+pub struct PotentialPlane {
+    pub visible: bool,
+    // The generated systems orbit in y = 0, a little below keeps the plane clear of the bodies
+    pub height: f32,
+    // Contour lines per doubling of the potential
+    pub contour_density: f32,
+}
+
+impl Default for PotentialPlane {
+    fn default() -> Self {
+        Self {
+            visible: false,
+            height: -50.0,
+            contour_density: 3.0,
+        }
+    }
+}
+
+// Per frame settings that reach the shader through SceneInfo
+#[derive(Debug, Clone, Copy)]
+pub struct RenderSettings {
+    pub samples: u32,
+    pub mode: RenderMode,
+    pub potential: PotentialPlane,
+}
+
 pub struct Renderer<'a> {
     surface: wgpu::Surface<'a>,
     pub device: wgpu::Device,
@@ -19,15 +77,23 @@ pub struct Renderer<'a> {
     camera_bind_group: wgpu::BindGroup,
     pub camera_buffer: wgpu::Buffer,
     planet_bind_group: wgpu::BindGroup,
-    pub planet_buffer: wgpu::Buffer,
+    planet_bind_group_layout: wgpu::BindGroupLayout,
+    planet_buffer: wgpu::Buffer,
+    bvh_buffer: wgpu::Buffer,
     info_bind_group: wgpu::BindGroup,
     pub info_buffer: wgpu::Buffer,
     space_texture_bind_group: wgpu::BindGroup,
+    space_texture_bind_group_layout: wgpu::BindGroupLayout,
     depth_texture: wgpu::Texture,
     depth_texture_view: wgpu::TextureView,
     depth_texture_sampler: wgpu::Sampler,
     vertex_buffer: wgpu::Buffer,
     index_buffer: wgpu::Buffer,
+    imgui_renderer: imgui_wgpu::Renderer,
+    overlay: Overlay,
+    post: PostProcess,
+    post_targets: PostTargets,
+    pub post_settings: PostSettings,
 }
 
 impl<'a> Renderer<'a> {
@@ -36,6 +102,8 @@ impl<'a> Renderer<'a> {
         planets: &[RawPlanetData],
         camera: &Camera,
         info: SceneInfo,
+        sky_image: &image::RgbaImage,
+        imgui: &mut imgui::Context,
     ) -> Renderer<'a> {
         let instance = Instance::new(InstanceDescriptor {
             backends: Backends::PRIMARY,
@@ -87,57 +155,6 @@ impl<'a> Renderer<'a> {
 
         surface.configure(&device, &config);
 
-        let image_bytes = include_bytes!("../assets/space.jpg");
-        let image = image::load_from_memory(image_bytes).unwrap();
-        let image_rgba = image.to_rgba8();
-
-        let dimensions = image.dimensions();
-
-        let texture_size = wgpu::Extent3d {
-            width: dimensions.0,
-            height: dimensions.1,
-            depth_or_array_layers: 1,
-        };
-
-        let space_texture = device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("Space Texture"),
-            size: texture_size,
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
-            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
-            view_formats: &[],
-        });
-
-        queue.write_texture(
-            wgpu::ImageCopyTexture {
-                texture: &space_texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-                aspect: wgpu::TextureAspect::All,
-            },
-            &image_rgba,
-            wgpu::ImageDataLayout {
-                offset: 0,
-                bytes_per_row: Some(4 * dimensions.0),
-                rows_per_image: Some(dimensions.1),
-            },
-            texture_size,
-        );
-
-        let space_texture_view = space_texture.create_view(&wgpu::TextureViewDescriptor::default());
-        let space_image_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            label: Some("Space Image Sampelr"),
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Nearest,
-            mipmap_filter: wgpu::FilterMode::Nearest,
-            ..Default::default()
-        });
-
         let camera_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 label: Some("Camera Bind Group Layout"),
@@ -168,35 +185,30 @@ impl<'a> Renderer<'a> {
             }],
         });
 
+        let storage_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
         let planet_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 label: Some("Planet Bind Group Layout"),
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                }],
+                entries: &[storage_entry(0), storage_entry(1)],
             });
 
-        let planet_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Planet Buffer"),
-            contents: bytemuck::cast_slice(planets),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-        });
-
-        let planet_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Planet Bind Group"),
-            layout: &planet_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: wgpu::BindingResource::Buffer(planet_buffer.as_entire_buffer_binding()),
-            }],
-        });
+        let (planet_buffer, bvh_buffer) = Self::create_planet_buffers(&device, planets.len());
+        let planet_bind_group = Self::create_planet_bind_group(
+            &device,
+            &planet_bind_group_layout,
+            &planet_buffer,
+            &bvh_buffer,
+        );
+        Self::write_planet_buffers(&queue, &planet_buffer, &bvh_buffer, planets);
 
         let info_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
@@ -251,20 +263,8 @@ impl<'a> Renderer<'a> {
                 ],
             });
 
-        let texture_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Texture Bind Group"),
-            layout: &texture_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&space_texture_view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&space_image_sampler)
-                }
-            ],
-        });
+        let texture_bind_group =
+            Self::create_sky_bind_group(&device, &queue, &texture_bind_group_layout, sky_image);
 
         let bind_group_layouts = vec![
             &camera_bind_group_layout,
@@ -303,7 +303,7 @@ impl<'a> Renderer<'a> {
             entry_point: "main",
             compilation_options: Default::default(),
             targets: &[Some(wgpu::ColorTargetState {
-                format: config.format,
+                format: HDR_FORMAT,
                 blend: Some(wgpu::BlendState::ALPHA_BLENDING),
                 write_mask: wgpu::ColorWrites::ALL,
             })],
@@ -324,8 +324,9 @@ impl<'a> Renderer<'a> {
             },
             depth_stencil: Some(wgpu::DepthStencilState {
                 format: wgpu::TextureFormat::Depth32Float,
+                // The fullscreen pass writes every pixel's depth from the ray hit, nothing is under it
                 depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less,
+                depth_compare: wgpu::CompareFunction::Always,
                 stencil: wgpu::StencilState::default(),
                 bias: wgpu::DepthBiasState::default(),
             }),
@@ -340,7 +341,7 @@ impl<'a> Renderer<'a> {
         });
 
         let (depth_texture, depth_texture_view, depth_texture_sampler) =
-            Self::create_depth_texture(&device, &config);
+            Self::create_depth_texture(&device, config.width, config.height);
 
         let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Vertex Buffer"),
@@ -354,6 +355,20 @@ impl<'a> Renderer<'a> {
             usage: wgpu::BufferUsages::INDEX,
         });
 
+        let overlay = Overlay::new(&device);
+        let post = PostProcess::new(&device, config.format);
+        let post_targets = post.create_targets(&device, config.width, config.height);
+
+        let imgui_renderer = imgui_wgpu::Renderer::new(
+            imgui,
+            &device,
+            &queue,
+            imgui_wgpu::RendererConfig {
+                texture_format: config.format,
+                ..Default::default()
+            },
+        );
+
         Self {
             surface,
             device,
@@ -363,6 +378,7 @@ impl<'a> Renderer<'a> {
             render_pipeline,
             camera_bind_group,
             planet_bind_group,
+            planet_bind_group_layout,
             depth_texture,
             depth_texture_view,
             depth_texture_sampler,
@@ -370,19 +386,194 @@ impl<'a> Renderer<'a> {
             index_buffer,
             camera_buffer,
             planet_buffer,
+            bvh_buffer,
             info_bind_group,
             info_buffer,
             space_texture_bind_group: texture_bind_group,
+            space_texture_bind_group_layout: texture_bind_group_layout,
+            imgui_renderer,
+            overlay,
+            post,
+            post_targets,
+            post_settings: PostSettings::default(),
+        }
+    }
+
+    // Room for this many bodies and the hierarchy over them, at least one so the buffers aren't empty
+    fn create_planet_buffers(
+        device: &wgpu::Device,
+        capacity: usize,
+    ) -> (wgpu::Buffer, wgpu::Buffer) {
+        let capacity = capacity.max(1);
+        let planet_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Planet Buffer"),
+            size: (capacity * std::mem::size_of::<RawPlanetData>()) as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bvh_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("BVH Buffer"),
+            size: ((capacity * 2 - 1) * std::mem::size_of::<BvhNode>()) as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        (planet_buffer, bvh_buffer)
+    }
+
+    fn create_planet_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        planet_buffer: &wgpu::Buffer,
+        bvh_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Planet Bind Group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Buffer(
+                        planet_buffer.as_entire_buffer_binding(),
+                    ),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Buffer(bvh_buffer.as_entire_buffer_binding()),
+                },
+            ],
+        })
+    }
+
+    // Upload the bodies and a hierarchy built over them, the buffers grow when there are more bodies than fit
+    pub fn write_planets(&mut self, planets: &[RawPlanetData]) {
+        let capacity = self.planet_buffer.size() as usize / std::mem::size_of::<RawPlanetData>();
+        if planets.len() > capacity {
+            (self.planet_buffer, self.bvh_buffer) =
+                Self::create_planet_buffers(&self.device, planets.len());
+            self.planet_bind_group = Self::create_planet_bind_group(
+                &self.device,
+                &self.planet_bind_group_layout,
+                &self.planet_buffer,
+                &self.bvh_buffer,
+            );
+        }
+        Self::write_planet_buffers(&self.queue, &self.planet_buffer, &self.bvh_buffer, planets);
+    }
+
+    fn write_planet_buffers(
+        queue: &wgpu::Queue,
+        planet_buffer: &wgpu::Buffer,
+        bvh_buffer: &wgpu::Buffer,
+        planets: &[RawPlanetData],
+    ) {
+        if planets.is_empty() {
+            return;
+        }
+        queue.write_buffer(planet_buffer, 0, bytemuck::cast_slice(planets));
+        queue.write_buffer(bvh_buffer, 0, bytemuck::cast_slice(&bvh::build(planets)));
+    }
+
+    // Upload an equirectangular sky image, the shader samples it by ray direction
+    // Images bigger than the device allows are scaled down to fit
+    pub fn create_sky_bind_group(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &wgpu::BindGroupLayout,
+        image: &image::RgbaImage,
+    ) -> wgpu::BindGroup {
+        let max_size = device.limits().max_texture_dimension_2d;
+        let fitted = sky::fit_to_size(image, max_size);
+        if let Some(fitted) = &fitted {
+            let (width, height) = image.dimensions();
+            eprintln!(
+                "Sky image is {width}x{height}, larger than the {max_size} pixel texture limit, scaled down to {}x{}",
+                fitted.width(),
+                fitted.height()
+            );
         }
+        let image = fitted.as_ref().unwrap_or(image);
+        let dimensions = image.dimensions();
+
+        let texture_size = wgpu::Extent3d {
+            width: dimensions.0,
+            height: dimensions.1,
+            depth_or_array_layers: 1,
+        };
+
+        let space_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Space Texture"),
+            size: texture_size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &space_texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            image,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * dimensions.0),
+                rows_per_image: Some(dimensions.1),
+            },
+            texture_size,
+        );
+
+        let space_texture_view = space_texture.create_view(&wgpu::TextureViewDescriptor::default());
+        // Wraps horizontally so there is no seam where the panorama meets itself
+        let space_image_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Space Image Sampler"),
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Texture Bind Group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&space_texture_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&space_image_sampler)
+                }
+            ],
+        })
+    }
+
+    // Replace the sky while running
+    pub fn set_sky_image(&mut self, image: &image::RgbaImage) {
+        self.space_texture_bind_group = Self::create_sky_bind_group(
+            &self.device,
+            &self.queue,
+            &self.space_texture_bind_group_layout,
+            image,
+        );
     }
 
     pub fn create_depth_texture(
         device: &wgpu::Device,
-        config: &wgpu::SurfaceConfiguration,
+        width: u32,
+        height: u32,
     ) -> (wgpu::Texture, wgpu::TextureView, wgpu::Sampler) {
         let surface_size = wgpu::Extent3d {
-            width: config.width,
-            height: config.height,
+            width,
+            height,
             depth_or_array_layers: 1,
         };
         let depth_texture = device.create_texture(&wgpu::TextureDescriptor {
@@ -414,7 +605,48 @@ impl<'a> Renderer<'a> {
         (depth_texture, depth_texture_view, depth_texture_sampler)
     }
 
-    pub fn render(&self) {
+    // Draw the ray marched scene into an HDR color_view, shared by the window and offscreen targets
+    fn encode_scene_pass(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        color_view: &wgpu::TextureView,
+        depth_view: &wgpu::TextureView,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Render Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: color_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+
+        render_pass.set_pipeline(&self.render_pipeline);
+
+        render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.planet_bind_group, &[]);
+        render_pass.set_bind_group(2, &self.info_bind_group, &[]);
+        render_pass.set_bind_group(3, &self.space_texture_bind_group, &[]);
+
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+        render_pass.draw_indexed(0..(INDICES.len() as u32), 0, 0..1);
+    }
+
+    pub fn render(&mut self, ui_draw_data: &imgui::DrawData) {
         let mut encoder = self
             .device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor {
@@ -427,40 +659,42 @@ impl<'a> Renderer<'a> {
                 .texture
                 .create_view(&wgpu::TextureViewDescriptor::default());
 
+            self.encode_scene_pass(
+                &mut encoder,
+                &self.post_targets.hdr_view,
+                &self.depth_texture_view,
+            );
+            self.overlay.encode(
+                &self.queue,
+                &mut encoder,
+                &self.post_targets.hdr_view,
+                &self.depth_texture_view,
+                self.config.width as f32 / self.config.height as f32,
+            );
+            self.post.write_settings(&self.queue, &self.post_settings);
+            self.post
+                .encode(&mut encoder, &self.post_targets, &color_view);
+
+            // The UI is drawn in its own pass, on top of the scene and without depth testing
             {
-                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                    label: Some("Render Pass"),
+                let mut ui_render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("UI Render Pass"),
                     color_attachments: &[Some(wgpu::RenderPassColorAttachment {
                         view: &color_view,
                         resolve_target: None,
                         ops: wgpu::Operations {
-                            load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                            load: wgpu::LoadOp::Load,
                             store: wgpu::StoreOp::Store,
                         },
                     })],
-                    depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                        view: &self.depth_texture_view,
-                        depth_ops: Some(wgpu::Operations {
-                            load: wgpu::LoadOp::Clear(1.0),
-                            store: wgpu::StoreOp::Store,
-                        }),
-                        stencil_ops: None,
-                    }),
+                    depth_stencil_attachment: None,
                     timestamp_writes: None,
                     occlusion_query_set: None,
                 });
 
-                render_pass.set_pipeline(&self.render_pipeline);
-
-                render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
-                render_pass.set_bind_group(1, &self.planet_bind_group, &[]);
-                render_pass.set_bind_group(2, &self.info_bind_group, &[]);
-                render_pass.set_bind_group(3, &self.space_texture_bind_group, &[]);
-
-                render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-                render_pass
-                    .set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
-                render_pass.draw_indexed(0..(INDICES.len() as u32), 0, 0..1);
+                self.imgui_renderer
+                    .render(ui_draw_data, &self.queue, &self.device, &mut ui_render_pass)
+                    .expect("Failed to render UI");
             }
 
             self.queue.submit(Some(encoder.finish()));
@@ -468,6 +702,143 @@ impl<'a> Renderer<'a> {
         }
     }
 
+    // Lines to draw over the scene from now on, camera is what the planets are seen from this frame
+    pub fn set_overlay(&mut self, camera: RawCameraData, lines: &[LineVertex]) {
+        self.overlay
+            .set_lines(&self.device, &self.queue, camera, lines);
+    }
+
+    // Largest width or height that can be rendered offscreen
+    pub fn max_texture_size(&self) -> u32 {
+        self.device.limits().max_texture_dimension_2d
+    }
+
+    // Render the scene without the UI to an offscreen texture and read the pixels back, after bloom and tone mapping
+    // The scene info resolution should be set to width x height before calling this
+    // Draws only the scene into the window's targets and waits for it, returning how long that took
+    // in milliseconds. Nothing is allocated or read back so the time is the shader's
+    pub fn time_scene_pass(&self) -> f32 {
+        // Work already queued finishes first so it isn't counted
+        self.device.poll(wgpu::Maintain::Wait);
+        let start = std::time::Instant::now();
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Timed Command Encoder"),
+            });
+        self.encode_scene_pass(
+            &mut encoder,
+            &self.post_targets.hdr_view,
+            &self.depth_texture_view,
+        );
+        self.queue.submit(Some(encoder.finish()));
+        self.device.poll(wgpu::Maintain::Wait);
+
+        start.elapsed().as_secs_f32() * 1000.0
+    }
+
+    pub fn render_to_image(&self, width: u32, height: u32) -> image::RgbaImage {
+        let texture_size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+
+        // Same format as the window so the existing tone map pipeline can be reused
+        let color_texture = self.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Offscreen Color Texture"),
+            size: texture_size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: self.config.format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let color_view = color_texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let (_depth_texture, depth_view, _depth_sampler) =
+            Self::create_depth_texture(&self.device, width, height);
+
+        // Rows in a texture copy have to be padded to a multiple of 256 bytes
+        let unpadded_bytes_per_row = 4 * width;
+        let padded_bytes_per_row = unpadded_bytes_per_row
+            .div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+            * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+
+        let readback_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Readback Buffer"),
+            size: (padded_bytes_per_row * height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Offscreen Command Encoder"),
+            });
+
+        let post_targets = self.post.create_targets(&self.device, width, height);
+        self.encode_scene_pass(&mut encoder, &post_targets.hdr_view, &depth_view);
+        self.overlay.encode(
+            &self.queue,
+            &mut encoder,
+            &post_targets.hdr_view,
+            &depth_view,
+            width as f32 / height as f32,
+        );
+        self.post.write_settings(&self.queue, &self.post_settings);
+        self.post.encode(&mut encoder, &post_targets, &color_view);
+
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                texture: &color_texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &readback_buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: Some(height),
+                },
+            },
+            texture_size,
+        );
+
+        self.queue.submit(Some(encoder.finish()));
+
+        let buffer_slice = readback_buffer.slice(..);
+        buffer_slice.map_async(wgpu::MapMode::Read, |result| {
+            result.expect("Failed to map readback buffer")
+        });
+        self.device.poll(wgpu::Maintain::Wait);
+
+        let mut pixels = Vec::with_capacity((unpadded_bytes_per_row * height) as usize);
+        {
+            let padded_data = buffer_slice.get_mapped_range();
+            for row in padded_data.chunks(padded_bytes_per_row as usize) {
+                pixels.extend_from_slice(&row[..unpadded_bytes_per_row as usize]);
+            }
+        }
+        readback_buffer.unmap();
+
+        // Surfaces are often BGRA, the image crate wants RGBA
+        if matches!(
+            self.config.format,
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb
+        ) {
+            pixels
+                .chunks_exact_mut(4)
+                .for_each(|pixel| pixel.swap(0, 2));
+        }
+
+        image::RgbaImage::from_raw(width, height, pixels).expect("Readback size mismatch")
+    }
+
     pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
         if new_size.width > 0 && new_size.height > 0 {
             self.config.width = new_size.width;
@@ -477,10 +848,13 @@ impl<'a> Renderer<'a> {
             self.surface.configure(&self.device, &self.config);
 
             let (depth_texture, depth_texture_view, depth_texture_sampler) =
-                Self::create_depth_texture(&self.device, &self.config);
+                Self::create_depth_texture(&self.device, new_size.width, new_size.height);
             self.depth_texture = depth_texture;
             self.depth_texture_view = depth_texture_view;
             self.depth_texture_sampler = depth_texture_sampler;
+            self.post_targets =
+                self.post
+                    .create_targets(&self.device, new_size.width, new_size.height);
         }
     }
 }
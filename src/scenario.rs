@@ -0,0 +1,339 @@
+use nalgebra::Vector3;
+use rand::Rng;
+
+use crate::{
+    calculations::{splice_planets, Planet, GRAV},
+    clock::MAX_SUBSTEP,
+    orbit::{self, OrbitalElements},
+    surface::{Atmosphere, Ring, Surface, SurfaceType},
+    units::{Quantity, Units},
+};
+
+// Sun, planets and major moons at J2000, see the file for the sources
+const SOLAR_SYSTEM: &str = include_str!("../assets/solar_system.txt");
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Scenario {
+    // A star with --bodies - 1 generated planets
+    #[default]
+    Random,
+    SolarSystem,
+}
+
+impl Scenario {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "random" => Some(Self::Random),
+            "solar-system" | "solar_system" | "solarsystem" | "solar" => Some(Self::SolarSystem),
+            _ => None,
+        }
+    }
+
+    pub fn build(&self, bodies: usize) -> Vec<Planet> {
+        match self {
+            Self::Random => random_system(bodies),
+            Self::SolarSystem => parse_system(SOLAR_SYSTEM)
+                .unwrap_or_else(|err| panic!("Bundled solar system data is invalid: {err}")),
+        }
+    }
+
+    // Longest physics step that keeps the scenario's tightest orbits together
+    pub fn max_step(&self) -> f32 {
+        match self {
+            Self::Random => MAX_SUBSTEP,
+            // Io goes around Jupiter in about 0.12 time units
+            Self::SolarSystem => 0.001,
+        }
+    }
+}
+
+// A star with randomly generated planets in roughly circular orbits
+pub fn random_system(planet_count: usize) -> Vec<Planet> {
+    // Random number generator
+    let mut rng = rand::thread_rng();
+
+    let mut planets = (0..planet_count)
+        .map(|i| match i {
+            0 => {
+                let mut star =
+                    Planet::new(10000.0, [0.0, 0.0, 0.0], 35.0, [1.0, 132.0 / 255.0, 0.0]);
+                star.luminosity = 1.0;
+                star
+            }
+            _ => {
+                let mut planet = Planet::new(
+                    rng.gen_range(5.0..15.0),
+                    [rng.gen_range(100.0..500.0), 0.0, 0.0],
+                    rng.gen_range(5.0..15.0),
+                    [
+                        rng.gen_range(0.0..1.0),
+                        rng.gen_range(0.0..1.0),
+                        rng.gen_range(0.0..1.0),
+                    ],
+                );
+                planet.surface = Surface::random(&mut rng);
+                planet.axial_tilt = rng.gen_range(0.0..30.0_f32).to_radians();
+                // Some planets keep one face to the star, the rest turn once every 10 to 60 time units
+                if rng.gen_bool(0.25) {
+                    planet.tidal_lock = Some(0);
+                } else {
+                    planet.rot_vel = std::f32::consts::TAU / rng.gen_range(10.0..60.0);
+                }
+                // Lava worlds are left bare, about half of the rest get air
+                if planet.surface.kind != SurfaceType::Lava && rng.gen_bool(0.5) {
+                    planet.atmosphere = Some(Atmosphere::random(&mut rng));
+                }
+                if planet.surface.kind == SurfaceType::GasGiant && rng.gen_bool(0.7) {
+                    planet.ring = Some(Ring::random(&mut rng, planet.spin_axis()));
+                }
+                planet
+            }
+        })
+        .collect::<Vec<_>>();
+
+    // Set centripetal acceleration after initialized
+    for i in 1..planet_count {
+        let (this_planet, mut other_planets) = splice_planets(i, &mut planets);
+        this_planet.set_init_velocity(&mut other_planets);
+    }
+
+    // Locked bodies start out facing their partner, before the first step turns them
+    for i in 0..planet_count {
+        if let Some(target) = planets[i].tidal_lock {
+            let target = planets[target].pos;
+            planets[i].face_towards(target);
+        }
+    }
+
+    name_unnamed(&mut planets);
+    planets
+}
+
+// Name every body the scenario left unnamed, planets get a letter from b on like exoplanets
+// and are numbered past z
+pub fn name_unnamed(planets: &mut [Planet]) {
+    let mut letter = 0;
+    for planet in planets.iter_mut().filter(|planet| planet.name.is_empty()) {
+        planet.name = if planet.luminosity > 0.0 {
+            "Star".to_string()
+        } else {
+            letter += 1;
+            match char::from_u32('a' as u32 + letter) {
+                Some(suffix) if letter < 26 => format!("Planet {suffix}"),
+                _ => format!("Planet {}", letter + 1),
+            }
+        };
+    }
+}
+
+// A body read from a system file, in simulation units and the ecliptic frame until it's placed
+struct SystemBody {
+    planet: Planet,
+    parent: Option<usize>,
+    mass: f64,
+    pos: Vector3<f64>,
+    vel: Vector3<f64>,
+}
+
+// Bodies from a file laid out like assets/solar_system.txt, parents have to come before their moons
+// The result is centered on the barycenter and at rest
+pub fn parse_system(contents: &str) -> Result<Vec<Planet>, String> {
+    let mut bodies: Vec<SystemBody> = Vec::new();
+    let length = |au: f64| Units::Astronomical.to_sim(au, Quantity::Length);
+
+    for (line_number, line) in contents.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        let invalid = |what: &str| format!("Invalid {what} on line {}", line_number + 1);
+        if !(16..=17).contains(&fields.len()) {
+            return Err(format!(
+                "Body on line {} needs 16 fields and an optional ring, found {}",
+                line_number + 1,
+                fields.len()
+            ));
+        }
+        let number =
+            |index: usize, what: &str| fields[index].parse::<f64>().map_err(|_| invalid(what));
+        let angle = |index: usize, what: &str| number(index, what).map(f64::to_radians);
+
+        let name = fields[0];
+        let parent = match fields[1] {
+            "-" => None,
+            parent => Some(
+                bodies
+                    .iter()
+                    .position(|body| body.planet.name == parent)
+                    .ok_or_else(|| {
+                        format!("Unknown parent {parent} on line {}", line_number + 1)
+                    })?,
+            ),
+        };
+        let mass = Units::Si.to_sim(number(2, "mass")?, Quantity::Mass);
+        let radius = Units::Si.to_sim(number(3, "radius")? * 1000.0, Quantity::Length);
+        let (node, periapsis) = (angle(7, "node")?, angle(8, "periapsis")?);
+        let elements = OrbitalElements {
+            semi_major_axis: length(number(4, "semi-major axis")?),
+            eccentricity: number(5, "eccentricity")?,
+            inclination: angle(6, "inclination")?,
+            node,
+            argument_of_periapsis: periapsis - node,
+            mean_anomaly: angle(9, "mean longitude")? - periapsis,
+        };
+        let kind = match fields[12] {
+            "star" | "plain" => SurfaceType::Plain,
+            "rocky" => SurfaceType::Rocky,
+            "gas" => SurfaceType::GasGiant,
+            "icy" => SurfaceType::Icy,
+            "lava" => SurfaceType::Lava,
+            _ => return Err(invalid("surface")),
+        };
+        let color = [
+            number(13, "color")? as f32,
+            number(14, "color")? as f32,
+            number(15, "color")? as f32,
+        ];
+
+        let (pos, vel) = match parent {
+            Some(parent) => {
+                let parent = &bodies[parent];
+                let mu = GRAV as f64 * (parent.mass + mass);
+                let (pos, vel) = elements.to_state_vector(mu);
+                (parent.pos + pos, parent.vel + vel)
+            }
+            None => (Vector3::zeros(), Vector3::zeros()),
+        };
+
+        let mut planet = Planet::new(mass as f32, [0.0; 3], radius as f32, color);
+        planet.name = name.to_string();
+        planet.surface = Surface::new(kind, bodies.len() as u32 * 7919);
+        planet.axial_tilt = angle(10, "axial tilt")? as f32;
+        match fields[11] {
+            "locked" => planet.tidal_lock = parent,
+            _ => {
+                let day = Units::Si.to_sim(number(11, "rotation period")? * 3600.0, Quantity::Time);
+                planet.rot_vel = (std::f64::consts::TAU / day) as f32;
+            }
+        }
+        if fields[12] == "star" {
+            planet.luminosity = 1.0;
+        }
+        match fields.get(16) {
+            Some(&"ring") => {
+                planet.ring = Some(Ring {
+                    inner_radius: 1.24,
+                    outer_radius: 2.27,
+                    normal: planet.spin_axis(),
+                    color: [0.85, 0.78, 0.62],
+                    opacity: 0.8,
+                })
+            }
+            Some(_) => return Err(invalid("ring")),
+            None => {}
+        }
+
+        bodies.push(SystemBody {
+            planet,
+            parent,
+            mass,
+            pos,
+            vel,
+        });
+    }
+
+    // A planet's elements are for the barycenter of it and its moons, so move them all until it's there
+    for index in 0..bodies.len() {
+        if bodies[index].parent.is_none() {
+            continue;
+        }
+        let system = (0..bodies.len())
+            .filter(|&i| i == index || bodies[i].parent == Some(index))
+            .collect::<Vec<_>>();
+        let (pos, vel) = barycenter(system.iter().map(|&i| &bodies[i]));
+        let (pos_offset, vel_offset) = (pos - bodies[index].pos, vel - bodies[index].vel);
+        for i in system {
+            bodies[i].pos -= pos_offset;
+            bodies[i].vel -= vel_offset;
+        }
+    }
+
+    let (pos, vel) = barycenter(bodies.iter());
+    let mut planets = bodies
+        .into_iter()
+        .map(|body| {
+            let mut planet = body.planet;
+            planet.pos = orbit::ecliptic_to_world(body.pos - pos).cast();
+            planet.vel = orbit::ecliptic_to_world(body.vel - vel).cast();
+            planet.vel2 = planet.vel;
+            planet
+        })
+        .collect::<Vec<_>>();
+
+    for i in 0..planets.len() {
+        if let Some(target) = planets[i].tidal_lock {
+            let target = planets[target].pos;
+            planets[i].face_towards(target);
+        }
+    }
+    Ok(planets)
+}
+
+// Mass weighted position and velocity
+fn barycenter<'a>(bodies: impl Iterator<Item = &'a SystemBody>) -> (Vector3<f64>, Vector3<f64>) {
+    let (mut pos, mut vel, mut mass) = (Vector3::zeros(), Vector3::zeros(), 0.0);
+    for body in bodies {
+        pos += body.pos * body.mass;
+        vel += body.vel * body.mass;
+        mass += body.mass;
+    }
+    if mass > 0.0 {
+        (pos / mass, vel / mass)
+    } else {
+        (pos, vel)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SUN: &str = "Sun - 1.98847e30 695700 0 0 0 0 0 0 7.25 609.12 star 1.00 0.85 0.55";
+    const EARTH: &str = "Earth Sun 5.9722e24 6371.0 1.00000261 0.01671123 -0.00001531 0 102.93768193 100.46457166 23.44 23.934 rocky 0.25 0.45 0.80";
+
+    #[test]
+    fn reads_the_bundled_system() {
+        let planets = parse_system(SOLAR_SYSTEM).unwrap();
+        assert_eq!(planets.len(), 18);
+        assert_eq!(planets[0].name, "Sun");
+        assert_eq!(planets[3].name, "Earth");
+        assert!(planets[6].ring.is_some());
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        let cases = [
+            (EARTH.replace(" rocky", ""), "needs 16 fields"),
+            (
+                EARTH.replace("5.9722e24", "heavy"),
+                "Invalid mass on line 2",
+            ),
+            (
+                EARTH.replace("0.01671123", "0.0167.1123"),
+                "Invalid eccentricity on line 2",
+            ),
+            (EARTH.replace("Sun", "Sol"), "Unknown parent Sol on line 2"),
+            (
+                EARTH.replace("rocky", "marble"),
+                "Invalid surface on line 2",
+            ),
+            (format!("{EARTH} rings"), "Invalid ring on line 2"),
+        ];
+        for (earth, message) in cases {
+            let err = parse_system(&format!("{SUN}\n{earth}\n")).unwrap_err();
+            assert!(err.contains(message), "{err}");
+        }
+        assert!(parse_system(&format!("{SUN}\n{EARTH}\n")).is_ok());
+    }
+}
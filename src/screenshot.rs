@@ -0,0 +1,113 @@
+use std::path::{Path, PathBuf};
+
+use image::{imageops::FilterType, RgbaImage};
+
+#[derive(Debug, Clone)]
+pub struct ScreenshotSettings {
+    // Output resolution for supersampled screenshots, None uses the window size
+    pub size: Option<[u32; 2]>,
+    // Each output pixel is rendered from supersample x supersample pixels
+    pub supersample: u32,
+    pub directory: PathBuf,
+}
+
+impl Default for ScreenshotSettings {
+    fn default() -> Self {
+        Self {
+            size: None,
+            supersample: 2,
+            directory: PathBuf::from("screenshots"),
+        }
+    }
+}
+
+// The size to save at and the (possibly larger) size to render at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CaptureSize {
+    pub output: [u32; 2],
+    pub render: [u32; 2],
+}
+
+impl ScreenshotSettings {
+    pub fn capture_size(
+        &self,
+        window_size: [u32; 2],
+        supersampled: bool,
+        max_texture_size: u32,
+    ) -> CaptureSize {
+        if !supersampled {
+            return CaptureSize {
+                output: window_size,
+                render: window_size,
+            };
+        }
+
+        supersampled_size(
+            self.size.unwrap_or(window_size),
+            self.supersample,
+            max_texture_size,
+        )
+    }
+}
+
+// Render at factor times the output size, lowering the factor until the target fits on the GPU.
+// Outputs too big to render even once are rendered smaller with the same aspect ratio and scaled up
+pub fn supersampled_size(output: [u32; 2], factor: u32, max_texture_size: u32) -> CaptureSize {
+    let output = output.map(|side| side.max(1));
+
+    let largest_side = output[0].max(output[1]);
+    let render = if largest_side > max_texture_size {
+        output.map(|side| {
+            ((side as u64 * max_texture_size as u64 / largest_side as u64) as u32).max(1)
+        })
+    } else {
+        let factor = factor.clamp(1, max_texture_size / largest_side);
+        output.map(|side| side * factor)
+    };
+
+    CaptureSize { output, render }
+}
+
+// Scale the rendered image down to the output size, averaging the supersampled pixels
+pub fn downsample(image: RgbaImage, size: CaptureSize) -> RgbaImage {
+    if size.render == size.output {
+        image
+    } else {
+        image::imageops::resize(&image, size.output[0], size.output[1], FilterType::Triangle)
+    }
+}
+
+// Save as a PNG with a timestamped name in the given directory
+pub fn save(image: &RgbaImage, directory: &Path) -> image::ImageResult<PathBuf> {
+    std::fs::create_dir_all(directory)?;
+
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_millis())
+        .unwrap_or_default();
+    let path = directory.join(format!("screenshot_{timestamp}.png"));
+
+    image.save_with_format(&path, image::ImageFormat::Png)?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn supersampling_keeps_the_aspect_ratio() {
+        let size = supersampled_size([1920, 1080], 4, 8192);
+        assert_eq!(size.output, [1920, 1080]);
+        assert_eq!(size.render, [7680, 4320]);
+
+        // 3x would be 9000 wide, so it drops to 2x
+        let size = supersampled_size([3000, 1000], 3, 8192);
+        assert_eq!(size.render, [6000, 2000]);
+
+        // Too wide to render at full size, both sides shrink by the same ratio
+        let size = supersampled_size([16384, 4096], 2, 8192);
+        assert_eq!(size.output, [16384, 4096]);
+        assert_eq!(size.render, [8192, 2048]);
+    }
+}
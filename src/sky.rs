@@ -0,0 +1,65 @@
+use std::path::Path;
+
+use image::{imageops::FilterType, RgbaImage};
+use nalgebra::{Vector2, Vector3};
+
+// The starfield bundled with the app
+pub fn default_image() -> RgbaImage {
+    let image_bytes = include_bytes!("../assets/space.jpg");
+    image::load_from_memory(image_bytes).unwrap().to_rgba8()
+}
+
+// Any image the image crate can read, treated as an equirectangular panorama
+pub fn load_image(path: &Path) -> Result<RgbaImage, String> {
+    image::open(path)
+        .map(|image| image.to_rgba8())
+        .map_err(|err| format!("Failed to load sky image {}: {err}", path.display()))
+}
+
+// The image at path if one was given, otherwise the bundled starfield
+pub fn load_or_default(path: Option<&Path>) -> Result<RgbaImage, String> {
+    path.map_or_else(|| Ok(default_image()), load_image)
+}
+
+// A copy scaled down to fit within max_size on both sides, keeping its aspect ratio, or None if it already fits
+pub fn fit_to_size(image: &RgbaImage, max_size: u32) -> Option<RgbaImage> {
+    let (width, height) = image.dimensions();
+    if width <= max_size && height <= max_size {
+        return None;
+    }
+
+    let scale = max_size as f64 / width.max(height) as f64;
+    let fitted_width = ((width as f64 * scale).round() as u32).clamp(1, max_size);
+    let fitted_height = ((height as f64 * scale).round() as u32).clamp(1, max_size);
+    Some(image::imageops::resize(
+        image,
+        fitted_width,
+        fitted_height,
+        FilterType::Triangle,
+    ))
+}
+
+// Texture coordinates of a world space direction, must match sky_uv in fragment.wgsl
+// u goes once around the horizon starting behind the origin, v runs from straight up to straight down
+pub fn direction_to_uv(direction: Vector3<f32>) -> Vector2<f32> {
+    let u = direction.x.atan2(direction.z) / std::f32::consts::TAU + 0.5;
+    let v = direction.y.clamp(-1.0, 1.0).acos() / std::f32::consts::PI;
+    Vector2::new(u, v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fit_to_size_scales_down_only_when_needed() {
+        let image = RgbaImage::new(400, 100);
+        assert!(fit_to_size(&image, 400).is_none());
+
+        let fitted = fit_to_size(&image, 64).unwrap();
+        assert_eq!(fitted.dimensions(), (64, 16));
+
+        let tall = fit_to_size(&RgbaImage::new(3, 1000), 100).unwrap();
+        assert_eq!(tall.dimensions(), (1, 100));
+    }
+}
@@ -0,0 +1,135 @@
+use nalgebra::Vector3;
+use rand::Rng;
+
+// How fragment.wgsl colors a body, the discriminants are what the shader sees
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SurfaceType {
+    // A single flat color, what every body used to be
+    #[default]
+    Plain = 0,
+    Rocky = 1,
+    GasGiant = 2,
+    Icy = 3,
+    Lava = 4,
+}
+
+impl SurfaceType {
+    pub const ALL: [SurfaceType; 5] = [
+        SurfaceType::Plain,
+        SurfaceType::Rocky,
+        SurfaceType::GasGiant,
+        SurfaceType::Icy,
+        SurfaceType::Lava,
+    ];
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Surface {
+    pub kind: SurfaceType,
+    // Picks the noise pattern, two bodies with the same seed and type look the same
+    pub seed: u32,
+    // Number of features across the body, higher gives smaller continents, bands and cracks
+    pub feature_scale: f32,
+    // Terrain height as a fraction of the radius, 0 keeps a perfect sphere
+    pub displacement: f32,
+}
+
+impl Default for Surface {
+    fn default() -> Self {
+        Self {
+            kind: SurfaceType::Plain,
+            seed: 0,
+            feature_scale: 1.0,
+            displacement: 0.0,
+        }
+    }
+}
+
+impl Surface {
+    // A surface of the given type with middling parameters
+    pub fn new(kind: SurfaceType, seed: u32) -> Self {
+        let (feature_scale, displacement) = match kind {
+            SurfaceType::Plain => (1.0, 0.0),
+            SurfaceType::Rocky => (2.5, 0.02),
+            SurfaceType::GasGiant => (1.5, 0.0),
+            SurfaceType::Icy => (3.5, 0.005),
+            SurfaceType::Lava => (2.5, 0.01),
+        };
+
+        Self {
+            kind,
+            seed,
+            feature_scale,
+            displacement,
+        }
+    }
+
+    // Any of the textured types with parameters that suit it
+    pub fn random(rng: &mut impl Rng) -> Self {
+        let kind = SurfaceType::ALL[rng.gen_range(1..SurfaceType::ALL.len())];
+        let (feature_scale, displacement) = match kind {
+            SurfaceType::Plain => (1.0, 0.0),
+            SurfaceType::Rocky => (rng.gen_range(1.5..4.0), rng.gen_range(0.01..0.04)),
+            SurfaceType::GasGiant => (rng.gen_range(1.0..2.5), 0.0),
+            SurfaceType::Icy => (rng.gen_range(2.0..5.0), rng.gen_range(0.0..0.015)),
+            SurfaceType::Lava => (rng.gen_range(1.5..3.5), rng.gen_range(0.005..0.02)),
+        };
+
+        Self {
+            kind,
+            seed: rng.gen(),
+            feature_scale,
+            displacement,
+        }
+    }
+}
+
+// A shell of gas around a body that scatters light from the emissive bodies
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Atmosphere {
+    // Thickness as a fraction of the radius
+    pub height: f32,
+    // How much light the air scatters looking straight up from the ground
+    pub density: f32,
+    // Scattering strength per channel, (0.2, 0.45, 1.0) gives an Earth-like blue sky and red sunsets
+    pub color: [f32; 3],
+}
+
+impl Atmosphere {
+    // Earth-like blue, a hazy orange or a greenish one
+    pub fn random(rng: &mut impl Rng) -> Self {
+        let colors = [[0.2, 0.45, 1.0], [1.0, 0.6, 0.25], [0.3, 1.0, 0.7]];
+        Self {
+            height: rng.gen_range(0.04..0.1),
+            density: rng.gen_range(0.2..0.8),
+            color: colors[rng.gen_range(0..colors.len())],
+        }
+    }
+}
+
+// A flat ring around a body, in the plane through its center
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ring {
+    // Both radii are multiples of the body's radius
+    pub inner_radius: f32,
+    pub outer_radius: f32,
+    pub normal: Vector3<f32>,
+    pub color: [f32; 3],
+    // Opacity of the densest bands, the shader breaks the ring into bands and gaps from the body's seed
+    pub opacity: f32,
+}
+
+impl Ring {
+    // A pale ring around the equator of a body spinning about normal
+    pub fn random(rng: &mut impl Rng, normal: Vector3<f32>) -> Self {
+        let inner_radius = rng.gen_range(1.3..1.7);
+        let shade = rng.gen_range(0.6..0.9);
+        Self {
+            inner_radius,
+            outer_radius: inner_radius + rng.gen_range(0.6..1.3),
+            normal,
+            color: [shade, shade * 0.92, shade * 0.8],
+            opacity: rng.gen_range(0.5..0.9),
+        }
+    }
+}
@@ -0,0 +1,366 @@
+use imgui::{Condition, SliderFlags};
+
+use crate::{
+    benchmark::ModeComparison,
+    calculations::Integrator,
+    cli::MAX_SAMPLES,
+    clock::{SimulationClock, MAX_SPEED, MIN_SPEED},
+    overlay::{self, Label, OverlaySettings},
+    postprocess::{PostSettings, ToneMapping},
+    recorder::{RecordFormat, RecorderSettings, TrajectoryRecorder},
+    renderer::{PotentialPlane, RenderMode, RenderSettings},
+    replay::ReplayPlayer,
+    screenshot::ScreenshotSettings,
+    units::{Quantity, Units},
+};
+
+fn units_combo(ui: &imgui::Ui, units: &mut Units) {
+    let mut index = Units::ALL
+        .iter()
+        .position(|u| u == units)
+        .unwrap_or_default();
+    let labels = Units::ALL.map(|u| u.label());
+    if ui.combo_simple_string("Units", &mut index, &labels) {
+        *units = Units::ALL[index];
+    }
+    let [length, mass, time] =
+        [Quantity::Length, Quantity::Mass, Quantity::Time].map(|q| units.unit(q));
+    ui.text_disabled(format!(
+        "G = {:.5e} {length}^3 / ({mass} {time}^2)",
+        units.grav()
+    ));
+}
+
+pub fn time_controls(ui: &imgui::Ui, clock: &mut SimulationClock, units: &mut Units) {
+    ui.window("Time")
+        .position([10.0, 10.0], Condition::FirstUseEver)
+        .size([260.0, 190.0], Condition::FirstUseEver)
+        .build(|| {
+            ui.text(format!(
+                "Simulated time: {}",
+                units.format(clock.time, Quantity::Time)
+            ));
+
+            let pause_label = if clock.paused { "Play" } else { "Pause" };
+            if ui.button(pause_label) {
+                clock.toggle_pause();
+            }
+            ui.same_line();
+            if ui.button("Step") {
+                clock.single_step();
+            }
+            ui.same_line();
+            ui.disabled(!clock.integrator.reversible(), || {
+                ui.checkbox("Reverse", &mut clock.reversed);
+            });
+
+            let mut index = Integrator::ALL
+                .iter()
+                .position(|&integrator| integrator == clock.integrator)
+                .unwrap_or_default();
+            let labels = Integrator::ALL.map(|integrator| integrator.label());
+            if ui.combo_simple_string("Integrator", &mut index, &labels) {
+                clock.integrator = Integrator::ALL[index];
+            }
+            if !clock.integrator.reversible() {
+                ui.text_disabled("Reverse needs the Verlet integrator");
+            }
+
+            let mut speed = clock.speed;
+            if ui
+                .slider_config("Speed", MIN_SPEED, MAX_SPEED)
+                .flags(SliderFlags::LOGARITHMIC)
+                .display_format("%.2fx")
+                .build(&mut speed)
+            {
+                clock.set_speed(speed);
+            }
+            if !clock.paused && clock.is_limited() {
+                ui.text_colored(
+                    [1.0, 0.6, 0.2, 1.0],
+                    format!(
+                        "Running at {:.2}x, steps per frame are capped",
+                        clock.achieved_speed
+                    ),
+                );
+            }
+
+            units_combo(ui, units);
+            ui.text_disabled("Space: pause  .: step  +/-: speed  R: reverse");
+        });
+}
+
+// Returns true if recording should be started or stopped
+pub fn recording_controls(
+    ui: &imgui::Ui,
+    settings: &mut RecorderSettings,
+    recorder: Option<&TrajectoryRecorder>,
+    units: Units,
+) -> bool {
+    let mut toggled = false;
+
+    ui.window("Recording")
+        .position([10.0, 210.0], Condition::FirstUseEver)
+        .size([260.0, 130.0], Condition::FirstUseEver)
+        .build(|| {
+            match recorder {
+                Some(recorder) => {
+                    ui.text(format!("Recording to {}", recorder.path.display()));
+                    ui.text(format!("{} samples", recorder.samples));
+                    toggled = ui.button("Stop");
+                }
+                None => {
+                    let mut format_index = RecordFormat::ALL
+                        .iter()
+                        .position(|format| *format == settings.format)
+                        .unwrap_or_default();
+                    let labels = RecordFormat::ALL.map(|format| format.label());
+                    if ui.combo_simple_string("Format", &mut format_index, &labels) {
+                        settings.format = RecordFormat::ALL[format_index];
+                    }
+
+                    // Shown in the chosen units over the same range of simulated time
+                    let time = |value: f64| units.express(value, Quantity::Time) as f32;
+                    let mut interval = time(settings.interval);
+                    let display_format = format!("%.3g {}", units.unit(Quantity::Time));
+                    if ui
+                        .slider_config("Interval", time(0.01), time(100.0))
+                        .flags(SliderFlags::LOGARITHMIC)
+                        .display_format(&display_format)
+                        .build(&mut interval)
+                    {
+                        settings.interval = units.to_sim(interval as f64, Quantity::Time);
+                    }
+
+                    toggled = ui.button("Record");
+                }
+            }
+
+            ui.text_disabled("F5: start/stop recording");
+        });
+
+    toggled
+}
+
+pub fn replay_controls(ui: &imgui::Ui, player: &mut ReplayPlayer, units: &mut Units) {
+    ui.window("Replay")
+        .position([10.0, 10.0], Condition::FirstUseEver)
+        .size([320.0, 170.0], Condition::FirstUseEver)
+        .build(|| {
+            let time = |value: f64| units.express(value, Quantity::Time) as f32;
+            let (start, end) = (
+                time(player.recording.start_time()),
+                time(player.recording.end_time()),
+            );
+
+            let mut shown_time = time(player.time);
+            let display_format = format!("%.3g {}", units.unit(Quantity::Time));
+            if ui
+                .slider_config("Time", start, end)
+                .display_format(&display_format)
+                .build(&mut shown_time)
+            {
+                player.time = units.to_sim(shown_time as f64, Quantity::Time);
+            }
+
+            let play_label = if player.playing { "Pause" } else { "Play" };
+            if ui.button(play_label) {
+                player.process_key(winit::keyboard::KeyCode::Space);
+            }
+            ui.same_line();
+            ui.checkbox("Loop", &mut player.looping);
+
+            let mut speed = player.speed;
+            if ui
+                .slider_config("Speed", MIN_SPEED, MAX_SPEED)
+                .flags(SliderFlags::LOGARITHMIC)
+                .display_format("%.2fx")
+                .build(&mut speed)
+            {
+                player.set_speed(speed);
+            }
+
+            units_combo(ui, units);
+            ui.text_disabled("Space: play  Left/Right: scrub  +/-: speed  L: loop");
+        });
+}
+
+// Returns true if a supersampled screenshot should be taken
+pub fn screenshot_controls(
+    ui: &imgui::Ui,
+    settings: &mut ScreenshotSettings,
+    window_size: [u32; 2],
+) -> bool {
+    let mut capture = false;
+
+    ui.window("Screenshot")
+        .position([10.0, 350.0], Condition::FirstUseEver)
+        .size([260.0, 150.0], Condition::FirstUseEver)
+        .build(|| {
+            let mut use_window_size = settings.size.is_none();
+            if ui.checkbox("Window size", &mut use_window_size) {
+                settings.size = if use_window_size {
+                    None
+                } else {
+                    Some(window_size)
+                };
+            }
+
+            if let Some(size) = &mut settings.size {
+                let mut width = size[0] as i32;
+                let mut height = size[1] as i32;
+                if ui.input_int("Width", &mut width).build() {
+                    size[0] = width.max(1) as u32;
+                }
+                if ui.input_int("Height", &mut height).build() {
+                    size[1] = height.max(1) as u32;
+                }
+            }
+
+            ui.slider("Supersample", 1, 8, &mut settings.supersample);
+
+            capture = ui.button("Capture");
+            ui.text_disabled("F12: window size  Shift+F12: these settings");
+        });
+
+    capture
+}
+
+pub fn overlay_controls(
+    ui: &imgui::Ui,
+    settings: &mut OverlaySettings,
+    potential: &mut PotentialPlane,
+) {
+    ui.window("Overlays")
+        .position([280.0, 10.0], Condition::FirstUseEver)
+        .size([240.0, 220.0], Condition::FirstUseEver)
+        .build(|| {
+            ui.checkbox("Velocity", &mut settings.velocity);
+            ui.checkbox("Acceleration", &mut settings.acceleration);
+            ui.checkbox("Grid and scale", &mut settings.grid);
+            ui.checkbox("Names", &mut settings.labels);
+
+            ui.checkbox("Potential plane", &mut potential.visible);
+            if potential.visible {
+                ui.slider("Height", -500.0, 500.0, &mut potential.height);
+                ui.slider("Contours", 0.5, 10.0, &mut potential.contour_density);
+            }
+
+            ui.text_disabled("V: velocity  F: acceleration");
+            ui.text_disabled("G: grid  L: names  P: plane");
+        });
+}
+
+// Longest the scale bar gets, it shrinks to the nearest round distance
+const SCALE_BAR_PIXELS: f32 = 160.0;
+
+// Bar in the bottom right corner spanning a round distance in units
+pub fn scale_bar(ui: &imgui::Ui, units_per_pixel: f32, units: Units) {
+    let per_pixel = units.express(units_per_pixel as f64, Quantity::Length);
+    let length = overlay::scale_bar_length(per_pixel * SCALE_BAR_PIXELS as f64);
+    let pixels = (length / per_pixel) as f32;
+    let [width, height] = ui.io().display_size;
+    let (right, y) = (width - 20.0, height - 20.0);
+    let left = right - pixels;
+    let color = [1.0, 1.0, 1.0, 0.9];
+
+    let draw_list = ui.get_foreground_draw_list();
+    draw_list
+        .add_line([left, y], [right, y], color)
+        .thickness(2.0)
+        .build();
+    for x in [left, right] {
+        draw_list
+            .add_line([x, y - 5.0], [x, y + 5.0], color)
+            .thickness(2.0)
+            .build();
+    }
+    // Round lengths are written out in full unless they need a lot of zeros
+    let unit = units.unit(Quantity::Length);
+    let label = if (1e-3..1e5).contains(&length) {
+        let decimals = (-length.log10().floor()).max(0.0) as usize;
+        format!("{length:.decimals$} {unit}")
+    } else {
+        format!("{length:.0e} {unit}")
+    };
+    draw_list.add_text([left, y - 22.0], color, label);
+}
+
+// Returns true if the render modes should be compared
+pub fn display_controls(
+    ui: &imgui::Ui,
+    settings: &mut PostSettings,
+    render_settings: &mut RenderSettings,
+    frame_ms: f32,
+    comparison: Option<&ModeComparison>,
+) -> bool {
+    let mut compare = false;
+
+    ui.window("Display")
+        .position([10.0, 510.0], Condition::FirstUseEver)
+        .size([260.0, 250.0], Condition::FirstUseEver)
+        .build(|| {
+            ui.text(format!(
+                "Frame time: {frame_ms:.2} ms ({:.0} fps)",
+                1000.0 / frame_ms.max(1e-3)
+            ));
+
+            let mut mode_index = RenderMode::ALL
+                .iter()
+                .position(|mode| *mode == render_settings.mode)
+                .unwrap_or_default();
+            let labels = RenderMode::ALL.map(|mode| mode.label());
+            if ui.combo_simple_string("Render mode", &mut mode_index, &labels) {
+                render_settings.mode = RenderMode::ALL[mode_index];
+            }
+            ui.slider("Samples", 1, MAX_SAMPLES, &mut render_settings.samples);
+
+            let mut tone_mapping_index = ToneMapping::ALL
+                .iter()
+                .position(|tone_mapping| *tone_mapping == settings.tone_mapping)
+                .unwrap_or_default();
+            let labels = ToneMapping::ALL.map(|tone_mapping| tone_mapping.label());
+            if ui.combo_simple_string("Tone mapping", &mut tone_mapping_index, &labels) {
+                settings.tone_mapping = ToneMapping::ALL[tone_mapping_index];
+            }
+
+            ui.slider_config("Exposure", 0.05, 20.0)
+                .flags(SliderFlags::LOGARITHMIC)
+                .build(&mut settings.exposure);
+            ui.slider("Bloom", 0.0, 2.0, &mut settings.bloom_strength);
+            ui.slider("Threshold", 0.0, 4.0, &mut settings.bloom_threshold);
+
+            compare = ui.button("Compare render modes");
+            if let Some(comparison) = comparison {
+                ui.text(format!(
+                    "Ray march {:.2} ms, analytic {:.2} ms",
+                    comparison.ray_march_ms, comparison.analytic_ms
+                ));
+                ui.text(format!(
+                    "Difference: mean {:.2}, max {}, {:.2}% of pixels",
+                    comparison.mean_difference,
+                    comparison.max_difference,
+                    comparison.differing_pixels * 100.0
+                ));
+            }
+        });
+
+    compare
+}
+
+// Drawn behind the windows so a label never covers a control
+pub fn body_labels(ui: &imgui::Ui, labels: &[Label]) {
+    let draw_list = ui.get_background_draw_list();
+    for label in labels {
+        let [width, height] = ui.calc_text_size(&label.text);
+        let [x, y] = label.position;
+        let position = [x - width / 2.0, y - height];
+        // Dark outline so names read over bright bodies and the sky alike
+        draw_list.add_text(
+            [position[0] + 1.0, position[1] + 1.0],
+            [0.0, 0.0, 0.0, label.alpha],
+            &label.text,
+        );
+        draw_list.add_text(position, [1.0, 1.0, 1.0, label.alpha], &label.text);
+    }
+}
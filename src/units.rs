@@ -0,0 +1,170 @@
+use crate::calculations::GRAV;
+
+// Gravitational constant in m^3 / (kg s^2)
+pub const SI_GRAV: f64 = 6.67430e-11;
+pub const ASTRONOMICAL_UNIT: f64 = 1.495978707e11; // m
+pub const SOLAR_MASS: f64 = 1.98847e30; // kg
+pub const JULIAN_YEAR: f64 = 365.25 * 86400.0; // s
+
+// What the simulation's own units stand for, so they can be converted to the others
+// The generated systems put a Sun-like star of 10000 mass units at the origin with planets
+// 100 to 500 length units out, so a length unit is 0.01 AU and a mass unit 1e-4 solar masses
+// The time unit follows from GRAV, an orbit at 100 units takes about a year
+const SIM_LENGTH: f64 = ASTRONOMICAL_UNIT / 100.0; // m
+const SIM_MASS: f64 = SOLAR_MASS / 10000.0; // kg
+
+fn sim_time() -> f64 {
+    (GRAV as f64 * SIM_LENGTH.powi(3) / (SI_GRAV * SIM_MASS)).sqrt() // s
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quantity {
+    Length,
+    Mass,
+    Time,
+    Velocity,
+}
+
+// The unit system times, distances and masses are shown, recorded and read in
+// The simulation always runs in its own units, these only change how values are stated
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Units {
+    #[default]
+    Simulation,
+    Si,
+    // AU, solar masses and years
+    Astronomical,
+    // Dimensionless with G = 1
+    NBody,
+}
+
+impl Units {
+    pub const ALL: [Units; 4] = [
+        Units::Simulation,
+        Units::Si,
+        Units::Astronomical,
+        Units::NBody,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "simulation" | "sim" => Some(Self::Simulation),
+            "si" => Some(Self::Si),
+            "astronomical" | "astro" | "au" => Some(Self::Astronomical),
+            "nbody" | "n-body" => Some(Self::NBody),
+            _ => None,
+        }
+    }
+
+    // Written to recordings and accepted by from_name
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Simulation => "simulation",
+            Self::Si => "si",
+            Self::Astronomical => "astronomical",
+            Self::NBody => "nbody",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Simulation => "Simulation",
+            Self::Si => "SI (m, kg, s)",
+            Self::Astronomical => "Astronomical (AU, Msun, yr)",
+            Self::NBody => "N-body (G = 1)",
+        }
+    }
+
+    pub fn unit(&self, quantity: Quantity) -> &'static str {
+        match (self, quantity) {
+            (Self::Simulation, Quantity::Length) => "units",
+            (Self::Simulation, Quantity::Mass) => "mass units",
+            (Self::Simulation, Quantity::Time) => "time units",
+            (Self::Simulation, Quantity::Velocity) => "units/time unit",
+            (Self::Si, Quantity::Length) => "m",
+            (Self::Si, Quantity::Mass) => "kg",
+            (Self::Si, Quantity::Time) => "s",
+            (Self::Si, Quantity::Velocity) => "m/s",
+            (Self::Astronomical, Quantity::Length) => "AU",
+            (Self::Astronomical, Quantity::Mass) => "Msun",
+            (Self::Astronomical, Quantity::Time) => "yr",
+            (Self::Astronomical, Quantity::Velocity) => "AU/yr",
+            (Self::NBody, Quantity::Length) => "L",
+            (Self::NBody, Quantity::Mass) => "M",
+            (Self::NBody, Quantity::Time) => "T",
+            (Self::NBody, Quantity::Velocity) => "L/T",
+        }
+    }
+
+    // Suffix for keys holding a quantity in this system, like the yr of time_yr
+    pub fn key(&self, quantity: Quantity) -> &'static str {
+        match (self, quantity) {
+            (Self::Simulation, _) => "sim",
+            (Self::Si, Quantity::Length) => "m",
+            (Self::Si, Quantity::Mass) => "kg",
+            (Self::Si, Quantity::Time) => "s",
+            (Self::Si, Quantity::Velocity) => "m_per_s",
+            (Self::Astronomical, Quantity::Length) => "AU",
+            (Self::Astronomical, Quantity::Mass) => "Msun",
+            (Self::Astronomical, Quantity::Time) => "yr",
+            (Self::Astronomical, Quantity::Velocity) => "AU_per_yr",
+            (Self::NBody, Quantity::Length) => "L",
+            (Self::NBody, Quantity::Mass) => "M",
+            (Self::NBody, Quantity::Time) => "T",
+            (Self::NBody, Quantity::Velocity) => "L_per_T",
+        }
+    }
+
+    // One length, mass and time unit of this system in simulation units
+    fn scale(&self) -> [f64; 3] {
+        match self {
+            Self::Simulation => [1.0, 1.0, 1.0],
+            Self::Si => [1.0 / SIM_LENGTH, 1.0 / SIM_MASS, 1.0 / sim_time()],
+            Self::Astronomical => [
+                ASTRONOMICAL_UNIT / SIM_LENGTH,
+                SOLAR_MASS / SIM_MASS,
+                JULIAN_YEAR / sim_time(),
+            ],
+            // Lengths and masses are kept, time is stretched until G comes out as 1
+            Self::NBody => [1.0, 1.0, 1.0 / (GRAV as f64).sqrt()],
+        }
+    }
+
+    // One unit of quantity in this system, in simulation units
+    fn factor(&self, quantity: Quantity) -> f64 {
+        let [length, mass, time] = self.scale();
+        match quantity {
+            Quantity::Length => length,
+            Quantity::Mass => mass,
+            Quantity::Time => time,
+            Quantity::Velocity => length / time,
+        }
+    }
+
+    // The gravitational constant in this system's units
+    pub fn grav(&self) -> f64 {
+        let [length, mass, time] = self.scale();
+        GRAV as f64 * mass * time * time / length.powi(3)
+    }
+
+    // A value given in this system, in simulation units
+    pub fn to_sim(self, value: f64, quantity: Quantity) -> f64 {
+        value * self.factor(quantity)
+    }
+
+    // A value in simulation units, in this system
+    pub fn express(self, value: f64, quantity: Quantity) -> f64 {
+        value / self.factor(quantity)
+    }
+
+    // A value in simulation units written out in this system, with its unit
+    pub fn format(&self, value: f64, quantity: Quantity) -> String {
+        let value = self.express(value, quantity);
+        let unit = self.unit(quantity);
+        if value != 0.0 && !(1e-2..1e5).contains(&value.abs()) {
+            format!("{value:.3e} {unit}")
+        } else {
+            format!("{value:.2} {unit}")
+        }
+    }
+}